@@ -0,0 +1,241 @@
+use std::{fmt, fs, path::{Path, PathBuf}};
+
+use chrono::Local;
+
+use super::file_tracker::FileTracker;
+use super::sync_change::{FileChange, SyncChange};
+use super::tracker_config::{LinkPolicy, PreserveMetadata};
+use super::version_store::VersionStore;
+use crate::helpers::file_ops::{copy_file_atomic, copy_link_atomic};
+
+// what to do with a file that changed on both sides since the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    NewestWins,
+    SourceWins,
+    DestWins,
+    // keep the source version in place and the destination version next to it with a `.conflict-<timestamp>` suffix
+    KeepBoth,
+    Skip,
+}
+
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Result<ConflictPolicy, String> {
+        match name.trim().to_lowercase().as_str() {
+            "newest-wins" | "newest" => Ok(ConflictPolicy::NewestWins),
+            "source-wins" | "src-wins" | "source" => Ok(ConflictPolicy::SourceWins),
+            "dest-wins" | "destination-wins" | "dest" => Ok(ConflictPolicy::DestWins),
+            "keep-both" | "both" => Ok(ConflictPolicy::KeepBoth),
+            "skip" | "skip-and-report" => Ok(ConflictPolicy::Skip),
+            _ => Err(format!(
+                "Unknown conflict policy {:?}, expected one of newest-wins, source-wins, dest-wins, keep-both, skip",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    KeptSource,
+    KeptDest,
+    // the destination version was saved under the given relative path
+    KeptBoth(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConflictRecord {
+    relative_path: String,
+    src_change: FileChange,
+    dest_change: FileChange,
+    resolution: Result<ConflictResolution, String>,
+}
+
+impl ConflictRecord {
+    pub fn get_relative_path(&self) -> &str {
+        &self.relative_path
+    }
+
+    pub fn get_src_change(&self) -> FileChange {
+        self.src_change
+    }
+
+    pub fn get_dest_change(&self) -> FileChange {
+        self.dest_change
+    }
+
+    pub fn get_resolution(&self) -> &Result<ConflictResolution, String> {
+        &self.resolution
+    }
+}
+
+// resolves conflicts between a source and destination directory
+pub struct ConflictResolver {
+    policy: ConflictPolicy,
+    src: PathBuf,
+    dest: PathBuf,
+    dry_run: bool,
+    timestamp: String,
+    // outside two way mode only the destination is ever written
+    two_way: bool,
+    // when set, files about to be overwritten or deleted are moved into the version store of their side first
+    versions: Option<(VersionStore, VersionStore)>,
+    // with links copied, a conflicting link is kept as a link
+    links: LinkPolicy,
+    // the attributes kept on the winning copy, the same the rest of the sync keeps
+    preserve: PreserveMetadata,
+}
+
+impl ConflictResolver {
+    pub fn new(policy: ConflictPolicy, src: &Path, dest: &Path, dry_run: bool) -> Self {
+        ConflictResolver {
+            policy,
+            src: src.to_path_buf(),
+            dest: dest.to_path_buf(),
+            dry_run,
+            timestamp: Local::now().format("%Y%m%d%H%M%S").to_string(),
+            two_way: false,
+            versions: None,
+            links: LinkPolicy::default(),
+            preserve: PreserveMetadata::default(),
+        }
+    }
+
+    pub fn set_two_way(&mut self, two_way: bool) {
+        self.two_way = two_way;
+    }
+
+    pub fn set_links(&mut self, links: LinkPolicy) {
+        self.links = links;
+    }
+
+    pub fn set_preserve(&mut self, preserve: PreserveMetadata) {
+        self.preserve = preserve;
+    }
+
+    pub fn set_versioning(&mut self, versioning: bool) {
+        self.versions = match versioning {
+            true => Some((VersionStore::new(&self.src), VersionStore::new(&self.dest))),
+            false => None,
+        };
+    }
+
+    pub fn get_policy(&self) -> ConflictPolicy {
+        self.policy
+    }
+
+    // apply the policy to a conflicting change, `src_file` / `dest_file` are the current state of each side
+    // a side is only copied while it still has the hash of its tracker, so an edit made meanwhile is never overwritten
+    pub fn resolve(&self, change: &SyncChange, src_file: Option<&FileTracker>, dest_file: Option<&FileTracker>) -> ConflictRecord {
+        let path = change.get_relative_path();
+        ConflictRecord {
+            relative_path: path.to_string(),
+            src_change: change.get_src_change(),
+            dest_change: change.get_dest_change(),
+            resolution: self.apply(path, src_file, dest_file),
+        }
+    }
+
+    fn apply(&self, path: &str, src_file: Option<&FileTracker>, dest_file: Option<&FileTracker>) -> Result<ConflictResolution, String> {
+        match self.policy {
+            ConflictPolicy::Skip => Ok(ConflictResolution::Skipped),
+            ConflictPolicy::SourceWins => self.keep_side(path, &self.src, &self.dest, src_file).map(|_| ConflictResolution::KeptSource),
+            ConflictPolicy::DestWins => self.keep_dest(path, dest_file),
+            ConflictPolicy::NewestWins => {
+                // a deleted file has no modification time, so the side that still has the file wins
+                let src_newer = match (src_file, dest_file) {
+                    (Some(s), Some(d)) => s.get_last_modified() >= d.get_last_modified(),
+                    (Some(_), None) => true,
+                    _ => false,
+                };
+                if src_newer {
+                    self.keep_side(path, &self.src, &self.dest, src_file).map(|_| ConflictResolution::KeptSource)
+                } else {
+                    self.keep_dest(path, dest_file)
+                }
+            }
+            ConflictPolicy::KeepBoth => {
+                let renamed = format!("{}.conflict-{}", path, self.timestamp);
+                if self.dry_run {
+                    return Ok(ConflictResolution::KeptBoth(renamed));
+                }
+                if let Some(dest_file) = dest_file {
+                    fs::rename(self.dest.join(path), self.dest.join(&renamed)).map_err(|e| e.to_string())?;
+                    if self.two_way {
+                        self.copy(&self.dest.join(&renamed), &self.src.join(&renamed), dest_file)?;
+                    }
+                }
+                if let Some(src_file) = src_file {
+                    self.copy(&self.src.join(path), &self.dest.join(path), src_file)?;
+                }
+                Ok(ConflictResolution::KeptBoth(renamed))
+            }
+        }
+    }
+
+    // the destination version is copied back to the source in two way mode, and left as it is otherwise
+    fn keep_dest(&self, path: &str, dest_file: Option<&FileTracker>) -> Result<ConflictResolution, String> {
+        if self.two_way {
+            self.keep_side(path, &self.dest, &self.src, dest_file)?;
+        }
+        Ok(ConflictResolution::KeptDest)
+    }
+
+    // make `to` match `from` for this path, deleting it from `to` if `from` no longer has it
+    fn keep_side(&self, path: &str, from: &Path, to: &Path, file: Option<&FileTracker>) -> Result<(), String> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let versions = self.versions.as_ref().map(|(src_versions, dest_versions)| match to == self.src.as_path() {
+            true => src_versions,
+            false => dest_versions,
+        });
+        if let Some(file) = file {
+            if let Some(versions) = versions {
+                versions.backup(path).map_err(|e| e.to_string())?;
+            }
+            return self.copy(&from.join(path), &to.join(path), file);
+        }
+        let target = to.join(path);
+        match versions {
+            Some(versions) => versions.backup_and_remove(path).map(|_| ()).map_err(|e| e.to_string()),
+            None if fs::symlink_metadata(&target).is_ok() => fs::remove_file(target).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    // copy `file`, found at `from`, to `to` the way the copy engine does
+    fn copy(&self, from: &Path, to: &Path, file: &FileTracker) -> Result<(), String> {
+        let expected_hash = Some(file.get_current_file_hash());
+        let result = match self.links == LinkPolicy::Copy && file.is_link() {
+            true => copy_link_atomic(from, to, expected_hash, self.preserve),
+            false => copy_file_atomic(from, to, expected_hash, self.preserve, |_| {}),
+        };
+        result.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ConflictPolicy::NewestWins => "newest-wins",
+            ConflictPolicy::SourceWins => "source-wins",
+            ConflictPolicy::DestWins => "dest-wins",
+            ConflictPolicy::KeepBoth => "keep-both",
+            ConflictPolicy::Skip => "skip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConflictResolution::KeptSource => write!(f, "kept source version"),
+            ConflictResolution::KeptDest => write!(f, "kept destination version"),
+            ConflictResolution::KeptBoth(renamed) => write!(f, "kept both, destination version saved as {}", renamed),
+            ConflictResolution::Skipped => write!(f, "skipped"),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::file_tracker::FileTracker;
+use super::sync_observer::SyncObserver;
+use super::tracker_config::{PreserveMetadata, VerifyLevel};
+use crate::helpers::file_ops::{copy_file_checked, copy_link_atomic, drop_cached_pages};
+
+pub const DEFAULT_COPY_WORKERS: usize = 4;
+// how many times a file that fails verification is copied again before it is reported
+pub const VERIFY_RETRIES: usize = 3;
+
+// a single file to copy from one root directory to the same relative path under another
+#[derive(Debug, Clone)]
+pub struct CopyJob {
+    relative_path: String,
+    from: PathBuf,
+    to: PathBuf,
+    size: u64,
+    // the hash the source had when it was scanned, the copy is rejected if the data read does not match it
+    expected_hash: Option<String>,
+    // recreate the source as a link instead of copying what it points to
+    link: bool,
+}
+
+impl CopyJob {
+    pub fn new(from: &Path, to: &Path, relative_path: &str, size: u64) -> Self {
+        CopyJob {
+            relative_path: relative_path.to_string(),
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            size,
+            expected_hash: None,
+            link: false,
+        }
+    }
+
+    pub fn with_expected_hash(mut self, hash: Option<&str>) -> Self {
+        self.expected_hash = hash.map(str::to_string);
+        self
+    }
+
+    pub fn with_link(mut self, link: bool) -> Self {
+        self.link = link;
+        self
+    }
+
+    pub fn is_link(&self) -> bool {
+        self.link
+    }
+
+    pub fn get_relative_path(&self) -> &str {
+        &self.relative_path
+    }
+
+    pub fn get_source(&self) -> PathBuf {
+        self.from.join(&self.relative_path)
+    }
+
+    pub fn get_target(&self) -> PathBuf {
+        self.to.join(&self.relative_path)
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_expected_hash(&self) -> Option<&str> {
+        self.expected_hash.as_deref()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    copied: usize,
+    bytes: u64,
+    // files read back from the destination and found to match the source
+    verified: usize,
+    failures: Vec<(String, String)>, // relative path and error
+}
+
+impl CopyReport {
+    pub fn get_copied(&self) -> usize {
+        self.copied
+    }
+
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn get_verified(&self) -> usize {
+        self.verified
+    }
+
+    pub fn get_failures(&self) -> &[(String, String)] {
+        &self.failures
+    }
+}
+
+// copies files on several worker threads while the calling thread reports the progress
+pub struct CopyEngine {
+    workers: usize,
+    verify: VerifyLevel,
+    preserve: PreserveMetadata,
+}
+
+impl CopyEngine {
+    pub fn new(workers: usize) -> Self {
+        CopyEngine {
+            workers: workers.max(1),
+            verify: VerifyLevel::None,
+            preserve: PreserveMetadata::default(),
+        }
+    }
+
+    // read every copied file back from the destination and copy it again when it does not match the source
+    pub fn with_verify(mut self, verify: VerifyLevel) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    // carry the times, owner and xattrs of each source file over to its copy
+    pub fn with_preserve(mut self, preserve: PreserveMetadata) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    pub fn get_workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn get_verify(&self) -> VerifyLevel {
+        self.verify
+    }
+
+    pub fn get_preserve(&self) -> PreserveMetadata {
+        self.preserve
+    }
+
+    pub fn run(&self, jobs: Vec<CopyJob>, observer: &mut dyn SyncObserver) -> CopyReport {
+        self.run_with(jobs, |_| {}, observer)
+    }
+
+    // like `run`, calling `on_copied` from the worker thread as soon as a file is in place
+    pub fn run_with(&self, jobs: Vec<CopyJob>, on_copied: impl Fn(&CopyJob) + Sync, observer: &mut dyn SyncObserver) -> CopyReport {
+        if jobs.is_empty() {
+            return CopyReport::default();
+        }
+        let total_files = jobs.len();
+        observer.on_copy_start(total_files, jobs.iter().map(|j| j.size).sum());
+
+        let queue = Mutex::new(jobs.into_iter());
+        let copied = AtomicUsize::new(0);
+        let verified = AtomicUsize::new(0);
+        let bytes_done = AtomicU64::new(0);
+        let failures = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            // the workers send each finished file so the observer is only called from this thread
+            let (sender, finished) = channel();
+            for _ in 0..self.workers.min(total_files) {
+                let sender = sender.clone();
+                let (queue, copied, verified, bytes_done, failures, on_copied) = (&queue, &copied, &verified, &bytes_done, &failures, &on_copied);
+                scope.spawn(move || loop {
+                    let job = match queue.lock().unwrap().next() {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let result = self.copy_verified(&job, bytes_done);
+                    match result {
+                        Ok(()) => {
+                            if self.verify != VerifyLevel::None {
+                                verified.fetch_add(1, Ordering::Relaxed);
+                            }
+                            let done = copied.fetch_add(1, Ordering::Relaxed) + 1;
+                            on_copied(&job);
+                            let _ = sender.send((job.relative_path, done, bytes_done.load(Ordering::Relaxed)));
+                        }
+                        Err(e) => failures.lock().unwrap().push((job.relative_path.clone(), e)),
+                    }
+                });
+            }
+            drop(sender);
+
+            // the channel disconnects once every worker is done
+            loop {
+                match finished.recv_timeout(Duration::from_millis(100)) {
+                    Ok((path, done, bytes)) => observer.on_file_copied(&path, done, bytes),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                observer.on_copy_progress(copied.load(Ordering::Relaxed), bytes_done.load(Ordering::Relaxed));
+            }
+        });
+
+        let report = CopyReport {
+            copied: copied.into_inner(),
+            bytes: bytes_done.into_inner(),
+            verified: verified.into_inner(),
+            failures: failures.into_inner().unwrap(),
+        };
+        observer.on_copy_finish(report.copied, report.bytes);
+        report
+    }
+
+    // copy the job, and with verification turned on keep copying until the copy matches or the retries run out
+    // the copy is checked before it replaces the destination, so a copy that never verifies leaves the old file in place
+    // a link is checked against its expected hash before it is put in place, there is no data to read back
+    // `bytes_done` moves with each chunk written, the bytes of an attempt that failed are taken back off
+    fn copy_verified(&self, job: &CopyJob, bytes_done: &AtomicU64) -> Result<(), String> {
+        if job.link {
+            let copied = copy_link_atomic(&job.get_source(), &job.get_target(), job.get_expected_hash(), self.preserve).map_err(|e| e.to_string())?;
+            bytes_done.fetch_add(copied, Ordering::Relaxed);
+            return Ok(());
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut written = 0;
+            let copied = copy_file_checked(
+                &job.get_source(),
+                &job.get_target(),
+                job.get_expected_hash(),
+                self.preserve,
+                |n| {
+                    written += n;
+                    bytes_done.fetch_add(n, Ordering::Relaxed);
+                },
+                |temp, copied| self.verify_copy(job, temp, copied),
+            );
+            let verified = match copied {
+                Ok(verified) => verified,
+                Err(e) => {
+                    bytes_done.fetch_sub(written, Ordering::Relaxed);
+                    return Err(e.to_string());
+                }
+            };
+            let Err(e) = verified else {
+                return Ok(());
+            };
+            bytes_done.fetch_sub(written, Ordering::Relaxed);
+            if attempt > VERIFY_RETRIES {
+                return Err(format!("verification failed after {} attempts: {}", attempt, e));
+            }
+            // the source may be fine and only this write went bad, as happens on flaky usb drives
+        }
+    }
+
+    // re-read the fsynced copy at `temp` and compare it with the source it was copied from
+    fn verify_copy(&self, job: &CopyJob, temp: &Path, copied: u64) -> Result<(), String> {
+        match self.verify {
+            VerifyLevel::None => Ok(()),
+            VerifyLevel::Size => {
+                let size = temp.metadata().map_err(|e| format!("could not read the copy: {}", e))?.len();
+                match size == copied {
+                    true => Ok(()),
+                    false => Err(format!("the copy has {} bytes, expected {}", size, copied)),
+                }
+            }
+            VerifyLevel::Hash => {
+                // read the copy from the drive, not from the pages its write left in memory
+                let _ = drop_cached_pages(temp);
+                let source_hash = match job.get_expected_hash() {
+                    Some(hash) => hash.to_string(),
+                    None => FileTracker::hash_file(&job.get_source()).map_err(|e| format!("could not read the source: {}", e))?,
+                };
+                let copy_hash = FileTracker::hash_file(temp).map_err(|e| format!("could not read the copy: {}", e))?;
+                match copy_hash == source_hash {
+                    true => Ok(()),
+                    false => Err(format!("the copy hash {} does not match the source hash {}", copy_hash, source_hash)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_of_failed_copies_are_not_counted() {
+        let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::write(src.path().join("good.txt"), "good").unwrap();
+        std::fs::write(src.path().join("changed.txt"), "changed since the scan").unwrap();
+        let good_hash = FileTracker::hash_file(&src.path().join("good.txt")).unwrap();
+        let jobs = vec![
+            CopyJob::new(src.path(), dest.path(), "good.txt", 4).with_expected_hash(Some(&good_hash)),
+            CopyJob::new(src.path(), dest.path(), "changed.txt", 22).with_expected_hash(Some("0000")),
+        ];
+
+        let report = CopyEngine::new(2).with_verify(VerifyLevel::Hash).run(jobs, &mut ());
+        assert_eq!(report.get_copied(), 1);
+        assert_eq!(report.get_verified(), 1);
+        assert_eq!(report.get_bytes(), 4);
+        assert_eq!(report.get_failures().len(), 1);
+        assert_eq!(report.get_failures()[0].0, "changed.txt");
+        assert!(!dest.path().join("changed.txt").exists());
+    }
+
+    // every read of this file returns a new uuid, so a copy of it never matches the source read back
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_copy_that_never_verifies_keeps_the_old_destination() {
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::write(dest.path().join("uuid"), "old").unwrap();
+        let jobs = vec![CopyJob::new(Path::new("/proc/sys/kernel/random"), dest.path(), "uuid", 37)];
+
+        let report = CopyEngine::new(1).with_verify(VerifyLevel::Hash).run(jobs, &mut ());
+        assert_eq!(report.get_copied(), 0);
+        assert_eq!(report.get_bytes(), 0);
+        assert_eq!(report.get_failures().len(), 1);
+        assert!(report.get_failures()[0].1.starts_with("verification failed"), "{}", report.get_failures()[0].1);
+        assert_eq!(std::fs::read_to_string(dest.path().join("uuid")).unwrap(), "old");
+        assert_eq!(std::fs::read_dir(dest.path()).unwrap().count(), 1);
+    }
+}
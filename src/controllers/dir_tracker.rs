@@ -1,6 +1,11 @@
 use super::file_tracker::FileTracker;
-use std::{collections::{HashMap, HashSet}, path::Path};
-use regex::Regex;
+use super::ignore_matcher::IgnoreMatcher;
+use super::tracker_config::LinkPolicy;
+use crate::helpers::error::SyncError;
+use crate::helpers::file_ops::{is_temp_file, write_file_atomic, TEMP_SUFFIX};
+use super::sync_change::{FileChange, SyncAction, SyncChange, SyncDirection};
+use std::{collections::{BTreeSet, HashMap}, fs, path::{Component, Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use rayon::prelude::*;
 use walkdir::WalkDir;
 use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,32 +19,108 @@ pub struct DirTracker {
     created: u64,
 
     // dir contents
-    ignore: HashSet<String>, // the ignore file is to be placed in the destination directory but refrerencing path relative to the source directory
+    ignore: Vec<String>, // the ignore file is to be placed in the destination directory but refrerencing path relative to the source directory
+    // the ignore patterns compiled once, in the order they were added
+    #[serde(skip)]
+    ignore_matcher: IgnoreMatcher,
     files: HashMap<String, FileTracker>, // Key is the file relative path
+
+    // unix timestamp of the last sync that updated tracker.json, 0 if it never synced
+    #[serde(default)]
+    last_synced: u64,
+
+    // what the scans do with symbolic links, saved so a later check reads them the same way
+    #[serde(default)]
+    links: LinkPolicy,
+
+    // rehash every file instead of reusing the hashes saved in tracker.json
+    #[serde(skip)]
+    checksum: bool,
+
+    // where the hashes of a directory without its own tracker.json are kept between syncs, see `set_cache_dir`
+    #[serde(skip)]
+    cache_file: Option<PathBuf>,
+
+    // files and directories the last scan could not read, they are left out of the sync instead of aborting it
+    #[serde(skip)]
+    errors: Vec<SyncError>,
+
+    // files and directories the last scan left out because of the ignore patterns, an ignored directory is one entry
+    #[serde(skip)]
+    ignored: Vec<IgnoredEntry>,
+
+    // temp files of copies a crashed or killed run did not finish, found by the last scan
+    #[serde(skip)]
+    temp_files: Vec<PathBuf>,
+}
+
+// what a walk found: the entries to track, what could not be read, what the ignore patterns left out and leftover temp files
+#[derive(Default)]
+struct Walk {
+    entries: Vec<walkdir::DirEntry>,
+    errors: Vec<SyncError>,
+    ignored: Vec<IgnoredEntry>,
+    temp_files: Vec<PathBuf>,
+}
+
+// a file or directory an ignore pattern kept out of the sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IgnoredEntry {
+    path: String,
+    dir: bool,
+    size: u64,
+}
+
+impl IgnoredEntry {
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    // 0 for a directory, its content is not walked
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
 }
 
 impl DirTracker {
-    pub fn new(path: &Path) -> Result<DirTracker, String> {
+    pub fn new(path: &Path) -> Result<DirTracker, SyncError> {
         if !path.exists() {
-            return Err("Path does not exist".to_string());
+            return Err(SyncError::NotFound { path: path.to_string_lossy().to_string() });
         }
-        let metadata = path.metadata().unwrap();
+        let metadata = path.metadata().map_err(|e| SyncError::io(path, e))?;
         if !metadata.is_dir() {
-            return Err("Path is not a directory".to_string());
+            return Err(SyncError::NotADirectory { path: path.to_string_lossy().to_string() });
         }
-        let path = path.to_str().unwrap().to_string();
         let size = metadata.len();
-        let last_modified = metadata.modified().unwrap().elapsed().unwrap().as_secs();
-        let created = metadata.created().unwrap_or(std::time::SystemTime::UNIX_EPOCH).elapsed().unwrap().as_secs();
+        // timestamps in the future (a skewed clock) count as just now
+        let last_modified = metadata.modified().ok().and_then(|t| t.elapsed().ok()).map(|d| d.as_secs()).unwrap_or(0);
+        let created = metadata.created().ok().and_then(|t| t.elapsed().ok()).map(|d| d.as_secs()).unwrap_or(0);
+        let path = path.to_str().ok_or_else(|| SyncError::invalid_name(path))?.to_string();
 
         let mut tracker = DirTracker {
             path,
             size,
             last_modified,
             created,
-            ignore: HashSet::from([".hard_sync_cli/*".to_string(), "hard_sync.ignore".to_string()]),
+            ignore: Vec::new(),
+            ignore_matcher: IgnoreMatcher::new(),
             files: HashMap::new(),
+            last_synced: 0,
+            links: LinkPolicy::default(),
+            checksum: false,
+            cache_file: None,
+            errors: Vec::new(),
+            ignored: Vec::new(),
+            temp_files: Vec::new(),
         };
+        tracker.add_ignore("/.hard_sync_cli/".to_string());
+        tracker.add_ignore("hard_sync.ignore".to_string());
+        // temp files of copies still in progress, or left by a crashed run
+        tracker.add_ignore(format!("*{}", TEMP_SUFFIX));
         let _ = tracker.load_ignore();
         Ok(tracker.clone())
     }
@@ -49,7 +130,27 @@ impl DirTracker {
     }
 
     pub fn add_ignore(&mut self, ignore: String) {
-        self.ignore.insert(ignore);
+        if self.ignore.contains(&ignore) {
+            return;
+        }
+        self.ignore_matcher.add(&ignore);
+        self.ignore.push(ignore);
+    }
+
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.checksum = checksum;
+    }
+
+    pub fn set_links(&mut self, links: LinkPolicy) {
+        self.links = links;
+    }
+
+    // keep the hashes in the .hard_sync_cli of `dir` when this directory is not initialized, like the source of a one way sync
+    // every source gets its own file there, named after its canonical path, so the next scan only rehashes what changed
+    pub fn set_cache_dir(&mut self, dir: &Path) {
+        let path = fs::canonicalize(&self.path).unwrap_or_else(|_| PathBuf::from(&self.path));
+        let id = FileTracker::calculate_hash(path.to_string_lossy().as_bytes());
+        self.cache_file = Some(dir.join(".hard_sync_cli").join("sources").join(format!("{}.json", &id[..16])));
     }
 
 }
@@ -72,7 +173,19 @@ impl DirTracker {
         self.created
     }
 
-    pub fn get_ignore(&self) -> HashSet<String> {
+    pub fn get_last_synced(&self) -> u64 {
+        self.last_synced
+    }
+
+    pub fn get_checksum(&self) -> bool {
+        self.checksum
+    }
+
+    pub fn get_links(&self) -> LinkPolicy {
+        self.links
+    }
+
+    pub fn get_ignore(&self) -> Vec<String> {
         self.ignore.clone()
     }
 
@@ -91,46 +204,194 @@ impl DirTracker {
     pub fn has_file(&self, file_path: &str) -> bool {
         self.files.contains_key(file_path)
     }
+
+    pub fn get_ignored(&self) -> &[IgnoredEntry] {
+        &self.ignored
+    }
+
+    pub fn get_temp_files(&self) -> &[PathBuf] {
+        &self.temp_files
+    }
+
+    pub fn get_errors(&self) -> &[SyncError] {
+        &self.errors
+    }
+
+    // whether the last scan failed to read `file_path` or a directory holding it, so its absence means nothing
+    pub fn is_unreadable(&self, file_path: &str) -> bool {
+        self.errors.iter().any(|e| e.is_file_error() && is_at_or_under(file_path, e.get_path()))
+    }
 }
 
 //  implentation to get / load all the files and sub directories
 impl DirTracker {
     pub fn import_files_from_directory(&mut self, recursive: bool) {
-        let dir = Path::new(&self.path);
-        let mut walker = WalkDir::new(dir);
-        if !recursive {
-            walker = walker.max_depth(1);
-        }
-        let walker = walker.into_iter();
+        // files whose size and mtime match the last saved state keep their stored hash
+        let cache = match self.checksum {
+            true => None,
+            false => self.load_last_synced().ok(),
+        };
+        // walking is cheap, so it stays on this thread and only the hashing is spread over the rayon pool
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        let Walk { entries, mut errors, ignored, temp_files } = self.walk(Path::new(&self.path), max_depth);
+        // a file that can not be read or named is skipped and reported, the rest of the scan goes on
+        let results: Vec<Result<(String, FileTracker), SyncError>> = entries
+            .par_iter()
+            .map(|entry| {
+                let (relative_path, file_path) = self.entry_paths(entry.path())?;
+                let cached = cache.as_ref().and_then(|c| c.get_file(&relative_path));
+                let file = read_entry(entry, file_path, cached).map_err(|e| SyncError::io(Path::new(&relative_path), e))?;
+                Ok((relative_path, file))
+            })
+            .collect();
         let mut files = HashMap::new();
-        for entry in walker
-            .filter_map(Result::ok)
-            .filter(|e| e.file_type().is_file())
-        {
-            let metadata = entry.metadata().unwrap();
-            if metadata.is_file() {
-                let file_path = entry.path();
-                files.insert(
-                    // relative path
-                    file_path
-                        .strip_prefix(Path::new(&self.path))
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                    FileTracker::new(file_path.to_str().unwrap()).unwrap(),
-                );
+        for result in results {
+            match result {
+                Ok((relative_path, file)) => {
+                    files.insert(relative_path, file);
+                }
+                Err(e) => errors.push(e),
             }
         }
+        errors.sort_by(|a, b| a.get_path().cmp(b.get_path()));
 
         self.files = files;
+        self.errors = errors;
+        self.ignored = ignored;
+        self.temp_files = temp_files;
+        self.group_hard_links();
+    }
+
+    // bring the tracked files in line with the disk after a sync
+    // files that changed since they were imported go through `FileTracker::update_hash` so the previous hash is kept
+    // files that can not be read keep their last known state, so an unreadable file is never saved as deleted
+    pub fn refresh_files(&mut self) {
+        let mut previous = std::mem::take(&mut self.files);
+        let Walk { entries, errors, .. } = self.walk(Path::new(&self.path), usize::MAX);
+        let unreadable = unreadable_paths(&errors);
+        for entry in entries {
+            let Ok((relative_path, file_path)) = self.entry_paths(entry.path()) else {
+                continue;
+            };
+            // a file that was replaced by a link or a directory, or the other way around, is read anew
+            let file = match previous.remove(&relative_path) {
+                Some(mut file) if is_same_kind(&file, &entry) => {
+                    let _ = file.refresh();
+                    file
+                }
+                _ => match read_entry(&entry, file_path, None) {
+                    Ok(file) => file,
+                    Err(_) => continue,
+                },
+            };
+            self.files.insert(relative_path, file);
+        }
+        for (relative_path, file) in previous {
+            if unreadable.iter().any(|dir| is_at_or_under(&relative_path, dir)) {
+                self.files.insert(relative_path, file);
+            }
+        }
+        self.group_hard_links();
+    }
+
+    // re-read only the given relative paths, each a file or a whole directory, after they changed on disk
+    // an empty path stands for the whole directory. returns the tracked files that were added, changed or removed
+    pub fn update_paths(&mut self, relative_paths: &[String]) -> Vec<String> {
+        let mut changed = BTreeSet::new();
+        for relative_path in relative_paths {
+            if !relative_path.is_empty() && self.is_ignored(relative_path) {
+                continue;
+            }
+            // forget everything tracked at or below the path, then add back what exists now
+            let prefix = format!("{}/", relative_path);
+            let stale: Vec<String> = self.files.keys()
+                .filter(|key| relative_path.is_empty() || *key == relative_path || key.starts_with(&prefix))
+                .cloned()
+                .collect();
+            let mut previous: HashMap<String, FileTracker> = stale.iter()
+                .filter_map(|key| self.files.remove_entry(key))
+                .collect();
+
+            let Walk { entries, errors, .. } = self.walk(&Path::new(&self.path).join(relative_path), usize::MAX);
+            let unreadable = unreadable_paths(&errors);
+            for entry in entries {
+                let Ok((key, file_path)) = self.entry_paths(entry.path()) else {
+                    continue;
+                };
+                // the file may be gone again by the time it is read, the next event will pick that up
+                let file = match previous.remove(&key) {
+                    Some(mut file) if is_same_kind(&file, &entry) => match (file.clone(), file.refresh()) {
+                        // a touch or chmod leaves the content alone, but a sync keeping the metadata still has work to do
+                        (before, Ok(false)) if file.has_same_attributes(&before) => file,
+                        (_, Ok(_)) => {
+                            changed.insert(key.clone());
+                            file
+                        }
+                        (_, Err(_)) => {
+                            changed.insert(key);
+                            continue;
+                        }
+                    },
+                    _ => match read_entry(&entry, file_path, None) {
+                        Ok(file) => {
+                            changed.insert(key.clone());
+                            file
+                        }
+                        Err(_) => continue,
+                    },
+                };
+                self.files.insert(key, file);
+            }
+            // whatever was tracked but is no longer there was removed, unless its directory could not be read
+            for (key, file) in previous {
+                match unreadable.iter().any(|dir| is_at_or_under(&key, dir)) {
+                    true => {
+                        self.files.insert(key, file);
+                    }
+                    false => {
+                        changed.insert(key);
+                    }
+                }
+            }
+        }
+        // a new or removed link can move files that did not change themselves into another group
+        changed.extend(self.group_hard_links());
+        changed.into_iter().collect()
+    }
+
+    // files that are hard links to the same data form a group, every file of it but the first by path records that first one
+    // only the files of this directory count, a link to a file elsewhere is synced as a file of its own
+    // returns the files whose group changed
+    fn group_hard_links(&mut self) -> Vec<String> {
+        let mut groups: HashMap<(u64, u64), Vec<&String>> = HashMap::new();
+        for (key, file) in &self.files {
+            if let Some(inode) = file.get_inode() {
+                groups.entry(inode).or_default().push(key);
+            }
+        }
+        let mut first_links = HashMap::new();
+        for mut keys in groups.into_values().filter(|keys| keys.len() > 1) {
+            keys.sort();
+            for key in &keys[1..] {
+                first_links.insert((*key).clone(), keys[0].clone());
+            }
+        }
+        let mut changed = Vec::new();
+        for (key, file) in self.files.iter_mut() {
+            let first_link = first_links.remove(key);
+            if file.get_hard_link() != first_link.as_deref() {
+                file.set_hard_link(first_link);
+                changed.push(key.clone());
+            }
+        }
+        changed
     }
 
     pub fn get_dir_diff(&self, other: &DirTracker) -> Vec<FileTracker> {
         let mut diff = Vec::new();
         for (key, file) in &self.files {
             if let Some(other_file) = other.files.get(key) {
-                if file.get_last_file_hash() != other_file.get_last_file_hash() && !other.is_ignored(file.get_relative_path(Path::new(&self.get_path()))) {
+                if file.get_current_file_hash() != other_file.get_current_file_hash() && !other.is_ignored(file.get_relative_path(Path::new(&self.get_path()))) {
                     diff.push(file.clone());
                 }
             } else {
@@ -139,73 +400,131 @@ impl DirTracker {
                 }
             }
         }
+        diff.sort_by(|a, b| a.get_path().cmp(b.get_path()));
         diff
     }
-    pub fn dir_initialized(&self) -> Result<(), String> {
+    // files in `other` that no longer exist here, skipping anything `other` ignores so it is never deleted
+    pub fn get_dir_deletions(&self, other: &DirTracker) -> Vec<FileTracker> {
+        let mut deletions: Vec<FileTracker> = other.files.iter()
+            .filter(|(key, _)| !self.files.contains_key(*key) && !other.is_ignored(key))
+            .map(|(_, file)| file.clone())
+            .collect();
+        deletions.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+        deletions
+    }
+
+    pub fn dir_initialized(&self) -> Result<(), SyncError> {
         // look for .hard_sync_cli in first level
         let dir = Path::new(&self.path);
         if !dir.exists() {
-            return Err("Directory does not exist".to_string());
+            return Err(SyncError::NotFound { path: self.path.clone() });
         }
         // check if .hard_sync_cli exists
         let hard_sync_cli = dir.join(".hard_sync_cli");
         if !hard_sync_cli.exists() {
-            return Err("Directory is not initalized".to_string());
+            return Err(SyncError::NotInitialized { path: self.path.clone() });
         }
         Ok(())
     }
 
-    pub fn setup_dir_config(&mut self) -> Result<(), String> {
+    pub fn setup_dir_config(&mut self) -> Result<(), SyncError> {
         // create .hard_sync_cli
         let dir = Path::new(&self.path);
         // check if .hard_sync_cli exists
         if !dir.exists() {
-            return Err("Directory does not exist".to_string());
+            return Err(SyncError::NotFound { path: self.path.clone() });
         }
         if !dir.is_dir() {
-            return Err("Path is not a directory".to_string());
+            return Err(SyncError::NotADirectory { path: self.path.clone() });
         }
         let hard_sync_cli = dir.join(".hard_sync_cli");
         if hard_sync_cli.exists() {
-            return Err("Directory is already initalized".to_string());
+            return Err(SyncError::AlreadyInitialized { path: self.path.clone() });
         }
         self.import_files_from_directory(true);
-        std::fs::create_dir(hard_sync_cli.clone()).unwrap();
+        std::fs::create_dir(&hard_sync_cli).map_err(|e| SyncError::io(&hard_sync_cli, e))?;
         // create the tracker.json file
-        let tracker = hard_sync_cli.join("tracker.json");
-        let tracker = std::fs::File::create(tracker).unwrap();
-        let tracker = serde_json::to_writer(tracker, self).unwrap();
-        Ok(())
+        self.write_tracker()
     }
 
-    //  to update the tracker.json file
-    pub fn update_tracker(&self) -> Result<(), String> {
-        let dir = Path::new(&self.path);
-        let hard_sync_cli = dir.join(".hard_sync_cli");
-        let tracker = hard_sync_cli.join("tracker.json");
-        // check if .hard_sync_cli/tracker.json exists
+    //  to update the tracker.json file after a sync
+    pub fn update_tracker(&mut self) -> Result<(), SyncError> {
+        self.dir_initialized()?;
+        self.last_synced = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.write_tracker()
+    }
+
+    // rescan what the sync changed and save it as the new last synced state
+    pub fn save_sync_state(&mut self) -> Result<(), SyncError> {
+        self.refresh_files();
+        self.update_tracker()
+    }
+
+    // save the scanned state to the cache file set with `set_cache_dir`, for a directory that has no tracker.json
+    pub fn save_cache(&self) -> Result<(), SyncError> {
+        let Some(cache) = &self.cache_file else {
+            return Ok(());
+        };
+        if let Some(parent) = cache.parent() {
+            fs::create_dir_all(parent).map_err(|e| SyncError::io(parent, e))?;
+        }
+        self.write_state(cache)
+    }
+
+    fn load_cache(&self) -> Result<DirTracker, SyncError> {
+        match &self.cache_file {
+            Some(cache) => Self::read_state(cache),
+            None => Err(SyncError::NotInitialized { path: self.path.clone() }),
+        }
+    }
+
+    // write tracker.json through a temp file and a rename so an interrupted write never leaves it half written
+    fn write_tracker(&self) -> Result<(), SyncError> {
+        self.write_state(&Path::new(&self.path).join(".hard_sync_cli").join("tracker.json"))
+    }
+
+    fn write_state(&self, tracker: &Path) -> Result<(), SyncError> {
+        let content = serde_json::to_vec(self).map_err(|e| SyncError::InvalidState {
+            path: tracker.to_string_lossy().to_string(),
+            message: e.to_string(),
+        })?;
+        write_file_atomic(tracker, &content).map_err(|e| SyncError::io(tracker, e))
+    }
+
+    // load the state written on the last sync from .hard_sync_cli/tracker.json
+    pub fn load_tracker(&self) -> Result<DirTracker, SyncError> {
+        Self::read_state(&Path::new(&self.path).join(".hard_sync_cli").join("tracker.json"))
+    }
+
+    // the state saved on the last sync, from tracker.json or, for a directory that has none, from the cache
+    pub fn load_last_synced(&self) -> Result<DirTracker, SyncError> {
+        self.load_tracker().or_else(|_| self.load_cache())
+    }
+
+    fn read_state(tracker: &Path) -> Result<DirTracker, SyncError> {
         if !tracker.exists() {
-            return Err("Tracker file does not exist".to_string());
-        }
-        // dont create a new file, just update the existing one
-        let tracker = std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(tracker)
-            .unwrap();
-        let tracker = serde_json::to_writer(tracker, self).unwrap();
-        Ok(())
+            return Err(SyncError::NotFound { path: tracker.to_string_lossy().to_string() });
+        }
+        let content = std::fs::read_to_string(tracker).map_err(|e| SyncError::io(tracker, e))?;
+        let mut tracker: DirTracker = serde_json::from_str(&content).map_err(|e| SyncError::InvalidState {
+            path: tracker.to_string_lossy().to_string(),
+            message: format!("invalid tracker file: {}", e),
+        })?;
+        for ignore in &tracker.ignore {
+            tracker.ignore_matcher.add(ignore);
+        }
+        Ok(tracker)
     }
 
-    pub fn load_ignore(&mut self) -> Result<(), String> {
+    pub fn load_ignore(&mut self) -> Result<(), SyncError> {
         let dir = Path::new(&self.path);
         let ignore = dir.join("hard_sync.ignore");
         if !ignore.exists() {
-            return Err("Ignore file does not exist".to_string());
+            return Err(SyncError::NotFound { path: ignore.to_string_lossy().to_string() });
         }
-        let ignore = std::fs::read_to_string(ignore).unwrap();
-        for line in ignore.lines() {
-            if line.starts_with("#") {
+        let content = std::fs::read_to_string(&ignore).map_err(|e| SyncError::io(&ignore, e))?;
+        for line in content.lines() {
+            if line.trim().is_empty() || line.starts_with("#") {
                 continue;
             }
             self.add_ignore(line.to_string());
@@ -213,13 +532,352 @@ impl DirTracker {
         Ok(())
     }
 
+    // `file_path` is relative to the directory, the patterns follow .gitignore rules
     pub fn is_ignored(&self, file_path: &str) -> bool {
-        for ignore in &self.ignore {
-            let reg = Regex::new(ignore).unwrap();
-            if reg.is_match(file_path) {
-                return true;
+        self.ignore_matcher.is_ignored(file_path, false)
+    }
+
+    pub fn is_dir_ignored(&self, dir_path: &str) -> bool {
+        self.ignore_matcher.is_ignored(dir_path, true)
+    }
+
+    // the key a file is tracked under and its full path, both have to be valid utf-8 to be stored in tracker.json
+    fn entry_paths<'a>(&self, path: &'a Path) -> Result<(String, &'a str), SyncError> {
+        let relative_path = path.strip_prefix(Path::new(&self.path)).unwrap_or(path);
+        match (relative_path.to_str(), path.to_str()) {
+            (Some(relative_path), Some(path)) => Ok((relative_path.to_string(), path)),
+            _ => Err(SyncError::invalid_name(relative_path)),
+        }
+    }
+
+    // walk `start`, the directory or a path below it
+    // the sync state, backups and journals in .hard_sync_cli are never synced, and ignored directories are not walked into
+    // links are kept as links, followed or skipped as the link policy says, and never lead the sync outside the directory
+    fn walk(&self, start: &Path, max_depth: usize) -> Walk {
+        let root = Path::new(&self.path);
+        let follow = self.links == LinkPolicy::Follow;
+        // a path that was removed has nothing left to walk, it is not unreadable
+        if start != root && fs::symlink_metadata(start).is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound) {
+            return Walk::default();
+        }
+        let canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut skipped = Vec::new();
+        let mut ignored = Vec::new();
+        let mut temp_files = Vec::new();
+        // a walk starting at a link below the root sees the link, like the walk of the whole directory does
+        let walker = WalkDir::new(start)
+            .max_depth(max_depth)
+            .follow_links(follow)
+            .follow_root_links(follow || start == root);
+        let walker = walker.into_iter().filter_entry(|e| {
+            if is_state_dir(e, root) {
+                return false;
+            }
+            if !e.file_type().is_dir() && is_temp_file(e.path()) {
+                temp_files.push(e.path().to_path_buf());
+                return false;
+            }
+            if self.is_entry_ignored(e) {
+                // the files hsync keeps for itself are left out without being reported
+                if !is_internal(e) {
+                    let dir = e.file_type().is_dir();
+                    let size = match dir {
+                        true => 0,
+                        false => e.metadata().map(|m| m.len()).unwrap_or(0),
+                    };
+                    ignored.push(IgnoredEntry { path: self.relative_display(e.path()), dir, size });
+                }
+                return false;
+            }
+            // a link that can not be resolved is left to the walk, which reports it
+            let outside = follow && e.path_is_symlink() && fs::canonicalize(e.path()).is_ok_and(|target| !target.starts_with(&canonical_root));
+            if outside {
+                skipped.push(self.skipped_link(e.path(), "it points outside the directory"));
+            }
+            !outside
+        });
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for entry in walker {
+            match entry {
+                Ok(entry) if entry.file_type().is_file() => entries.push(entry),
+                // the root itself is not an entry of the directory
+                Ok(entry) if entry.file_type().is_dir() => {
+                    if entry.path() != root {
+                        entries.push(entry);
+                    }
+                }
+                // only seen when links are not followed
+                Ok(entry) if entry.file_type().is_symlink() => match self.links {
+                    LinkPolicy::Copy if is_link_inside(root, entry.path()) => entries.push(entry),
+                    LinkPolicy::Copy => errors.push(self.skipped_link(entry.path(), "it points outside the directory")),
+                    _ => errors.push(self.skipped_link(entry.path(), "links are skipped")),
+                },
+                Ok(_) => {}
+                Err(e) => errors.push(self.walk_error(&e)),
+            }
+        }
+        errors.extend(skipped);
+        ignored.sort_by(|a, b| a.path.cmp(&b.path));
+        Walk { entries, errors, ignored, temp_files }
+    }
+
+    fn skipped_link(&self, path: &Path, reason: &str) -> SyncError {
+        SyncError::SkippedLink {
+            path: self.relative_display(path),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn relative_display(&self, path: &Path) -> String {
+        path.strip_prefix(Path::new(&self.path)).unwrap_or(path).to_string_lossy().to_string()
+    }
+
+    // a directory or file the walk could not read, reported relative to the root like the files
+    // while following links, a link back to a directory it is in and a link to nothing are skipped instead
+    fn walk_error(&self, e: &walkdir::Error) -> SyncError {
+        let path = e.path().unwrap_or(Path::new(&self.path));
+        if let Some(ancestor) = e.loop_ancestor() {
+            let reason = match self.relative_display(ancestor) {
+                ancestor if ancestor.is_empty() => "it loops back to the root directory".to_string(),
+                ancestor => format!("it loops back to {}", ancestor),
+            };
+            return self.skipped_link(path, &reason);
+        }
+        if self.links == LinkPolicy::Follow && fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) && !path.exists() {
+            return self.skipped_link(path, "it points to nothing");
+        }
+        let relative_path = path.strip_prefix(Path::new(&self.path)).unwrap_or(path);
+        SyncError::Io {
+            path: relative_path.to_string_lossy().to_string(),
+            kind: e.io_error().map(|io| io.kind()).unwrap_or(std::io::ErrorKind::Other),
+            message: e.io_error().map(|io| io.to_string()).unwrap_or_else(|| e.to_string()),
+        }
+    }
+
+    // used while walking so ignored directories are skipped as a whole
+    fn is_entry_ignored(&self, entry: &walkdir::DirEntry) -> bool {
+        let Ok(relative_path) = entry.path().strip_prefix(Path::new(&self.path)) else {
+            return false;
+        };
+        let Some(relative_path) = relative_path.to_str() else {
+            return false;
+        };
+        if relative_path.is_empty() {
+            return false;
+        }
+        match entry.file_type().is_dir() {
+            true => self.is_dir_ignored(relative_path),
+            false => self.is_ignored(relative_path),
+        }
+    }
+}
+
+//  implementation for two way (bidirectional) sync
+impl DirTracker {
+    // how a file changed in this directory since the `baseline` state was saved
+    pub fn get_file_change(&self, baseline: &DirTracker, file_path: &str) -> FileChange {
+        match (baseline.get_file(file_path), self.get_file(file_path)) {
+            (None, None) => FileChange::Unchanged,
+            (None, Some(_)) => FileChange::Added,
+            (Some(_), None) => FileChange::Deleted,
+            (Some(old), Some(new)) => {
+                if old.get_current_file_hash() != new.get_current_file_hash() {
+                    FileChange::Modified
+                } else {
+                    FileChange::Unchanged
+                }
+            }
+        }
+    }
+
+    // works out which side changed each file since the last sync and which way it has to go
+    // `self` is the source, `other` the destination, each with the baseline loaded from its own tracker.json
+    pub fn get_two_way_diff(&self, self_baseline: &DirTracker, other: &DirTracker, other_baseline: &DirTracker) -> Vec<SyncChange> {
+        let paths: BTreeSet<&String> = self.files.keys()
+            .chain(self_baseline.files.keys())
+            .chain(other.files.keys())
+            .chain(other_baseline.files.keys())
+            .collect();
+
+        let mut diff = Vec::new();
+        for path in paths {
+            if self.is_ignored(path) || other.is_ignored(path) {
+                continue;
             }
+            let src_file = self.get_file(path);
+            let dest_file = other.get_file(path);
+            // both sides already agree, nothing to move
+            let same = match (src_file, dest_file) {
+                (None, None) => true,
+                (Some(s), Some(d)) => s.get_current_file_hash() == d.get_current_file_hash(),
+                _ => false,
+            };
+            if same {
+                continue;
+            }
+
+            let src_change = self.get_file_change(self_baseline, path);
+            let dest_change = other.get_file_change(other_baseline, path);
+            let action = match (src_change, dest_change) {
+                // the baselines disagree, so this path has never been synced
+                (FileChange::Unchanged, FileChange::Unchanged) => match (src_file, dest_file) {
+                    (Some(_), None) if !other_baseline.has_file(path) => SyncAction::Copy(SyncDirection::SrcToDest),
+                    (None, Some(_)) if !self_baseline.has_file(path) => SyncAction::Copy(SyncDirection::DestToSrc),
+                    _ => SyncAction::Conflict,
+                },
+                (FileChange::Deleted, FileChange::Unchanged) => SyncAction::Delete(SyncDirection::SrcToDest),
+                (_, FileChange::Unchanged) => SyncAction::Copy(SyncDirection::SrcToDest),
+                (FileChange::Unchanged, FileChange::Deleted) => SyncAction::Delete(SyncDirection::DestToSrc),
+                (FileChange::Unchanged, _) => SyncAction::Copy(SyncDirection::DestToSrc),
+                // changed on both sides
+                _ => SyncAction::Conflict,
+            };
+            diff.push(SyncChange::new(path, src_change, dest_change, action));
+        }
+        diff
+    }
+}
+
+// whether the relative `path` is `dir` itself or inside it, an empty `dir` is the root and holds everything
+fn is_at_or_under(path: &str, dir: &str) -> bool {
+    dir.is_empty() || path == dir || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+// the .hard_sync_cli directory at the root of a synced directory, the walk may start below the root
+fn is_state_dir(entry: &walkdir::DirEntry, root: &Path) -> bool {
+    entry.file_type().is_dir() && entry.file_name() == ".hard_sync_cli" && entry.path().parent() == Some(root)
+}
+
+// ignored by the patterns every tracker starts with: the ignore file, the state directory and copies in progress
+fn is_internal(entry: &walkdir::DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    name == "hard_sync.ignore" || name == ".hard_sync_cli" || name.ends_with(TEMP_SUFFIX)
+}
+
+// the file or directory an entry of the walk is, or the link itself for a link kept as a link
+fn read_entry(entry: &walkdir::DirEntry, file_path: &str, cached: Option<&FileTracker>) -> std::io::Result<FileTracker> {
+    let file_type = entry.file_type();
+    match (file_type.is_symlink(), file_type.is_dir()) {
+        (true, _) => FileTracker::new_link(file_path),
+        (false, true) => FileTracker::new_dir(file_path),
+        (false, false) => FileTracker::new_with_cache(file_path, cached),
+    }
+}
+
+fn is_same_kind(file: &FileTracker, entry: &walkdir::DirEntry) -> bool {
+    file.is_link() == entry.file_type().is_symlink() && file.is_dir() == entry.file_type().is_dir()
+}
+
+// directories and files the walk could not read, tracked files under them keep their last known state
+fn unreadable_paths(errors: &[SyncError]) -> Vec<String> {
+    errors.iter().filter(|e| e.is_file_error()).map(|e| e.get_path().to_string()).collect()
+}
+
+// whether the target of the link at `path` stays inside `root` once recreated there
+// judged from the target alone since it may not exist yet, absolute targets would point back at the original
+fn is_link_inside(root: &Path, path: &Path) -> bool {
+    let (Ok(target), Some(parent)) = (fs::read_link(path), path.parent().and_then(|p| p.strip_prefix(root).ok())) else {
+        return false;
+    };
+    let mut depth = parent.components().count();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent_depth) => depth = parent_depth,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirTracker;
+    use crate::controllers::sync_change::{FileChange, SyncAction, SyncDirection};
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    // a directory holding `files`, scanned into a tracker
+    fn tracker(files: &[(&str, &str)]) -> (TempDir, DirTracker) {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            std::fs::write(dir.path().join(path), content).unwrap();
         }
-        false
+        let mut tracker = DirTracker::new(dir.path()).unwrap();
+        tracker.import_files_from_directory(true);
+        (dir, tracker)
+    }
+
+    fn actions(src: &DirTracker, src_base: &DirTracker, dest: &DirTracker, dest_base: &DirTracker) -> HashMap<String, (FileChange, FileChange, SyncAction)> {
+        src.get_two_way_diff(src_base, dest, dest_base)
+            .into_iter()
+            .map(|c| (c.get_relative_path().to_string(), (c.get_src_change(), c.get_dest_change(), c.get_action())))
+            .collect()
+    }
+
+    #[test]
+    fn two_way_diff_sends_each_change_the_way_it_has_to_go() {
+        let synced = [("same", "1"), ("src_mod", "1"), ("dest_mod", "1"), ("src_del", "1"), ("dest_del", "1")];
+        let (_b, base) = tracker(&synced);
+        let (_s, src) = tracker(&[("same", "1"), ("src_mod", "2"), ("dest_mod", "1"), ("dest_del", "1"), ("src_new", "1")]);
+        let (_d, dest) = tracker(&[("same", "1"), ("src_mod", "1"), ("dest_mod", "2"), ("src_del", "1"), ("dest_new", "1")]);
+
+        let diff = actions(&src, &base, &dest, &base);
+        let to_dest = SyncDirection::SrcToDest;
+        let to_src = SyncDirection::DestToSrc;
+        assert_eq!(diff.len(), 6);
+        assert!(!diff.contains_key("same"));
+        assert_eq!(diff["src_mod"], (FileChange::Modified, FileChange::Unchanged, SyncAction::Copy(to_dest)));
+        assert_eq!(diff["dest_mod"], (FileChange::Unchanged, FileChange::Modified, SyncAction::Copy(to_src)));
+        assert_eq!(diff["src_del"], (FileChange::Deleted, FileChange::Unchanged, SyncAction::Delete(to_dest)));
+        assert_eq!(diff["dest_del"], (FileChange::Unchanged, FileChange::Deleted, SyncAction::Delete(to_src)));
+        assert_eq!(diff["src_new"], (FileChange::Added, FileChange::Unchanged, SyncAction::Copy(to_dest)));
+        assert_eq!(diff["dest_new"], (FileChange::Unchanged, FileChange::Added, SyncAction::Copy(to_src)));
+    }
+
+    #[test]
+    fn two_way_diff_reports_changes_on_both_sides_as_conflicts() {
+        let (_b, base) = tracker(&[("both_mod", "1"), ("both_same", "1"), ("del_mod", "1")]);
+        let (_s, src) = tracker(&[("both_mod", "2"), ("both_same", "2"), ("both_new", "a")]);
+        let (_d, dest) = tracker(&[("both_mod", "3"), ("both_same", "2"), ("del_mod", "2"), ("both_new", "b")]);
+
+        let diff = actions(&src, &base, &dest, &base);
+        assert_eq!(diff.len(), 3);
+        // the same edit made on both sides is not a conflict
+        assert!(!diff.contains_key("both_same"));
+        assert_eq!(diff["both_mod"], (FileChange::Modified, FileChange::Modified, SyncAction::Conflict));
+        assert_eq!(diff["del_mod"], (FileChange::Deleted, FileChange::Modified, SyncAction::Conflict));
+        assert_eq!(diff["both_new"], (FileChange::Added, FileChange::Added, SyncAction::Conflict));
+    }
+
+    #[test]
+    fn two_way_diff_uses_each_side_baseline() {
+        // only the source was ever synced with `old`, so the destination never had it and it is copied back
+        let (_sb, src_base) = tracker(&[("old", "1")]);
+        let (_db, dest_base) = tracker(&[]);
+        let (_s, src) = tracker(&[("old", "1")]);
+        let (_d, dest) = tracker(&[]);
+        let diff = actions(&src, &src_base, &dest, &dest_base);
+        assert_eq!(diff["old"], (FileChange::Unchanged, FileChange::Unchanged, SyncAction::Copy(SyncDirection::SrcToDest)));
+
+        // both baselines had it, so the destination deleted it
+        let (_db, dest_base) = tracker(&[("old", "1")]);
+        let diff = actions(&src, &src_base, &dest, &dest_base);
+        assert_eq!(diff["old"], (FileChange::Unchanged, FileChange::Deleted, SyncAction::Delete(SyncDirection::DestToSrc)));
+    }
+
+    #[test]
+    fn two_way_diff_skips_ignored_paths() {
+        let (_b, base) = tracker(&[]);
+        let (_s, src) = tracker(&[("hard_sync.ignore", "*.tmp\n"), ("notes.tmp", "1"), ("notes.txt", "1")]);
+        let (_d, dest) = tracker(&[]);
+
+        let diff = actions(&src, &base, &dest, &base);
+        assert_eq!(diff.keys().collect::<Vec<_>>(), vec!["notes.txt"]);
     }
 }
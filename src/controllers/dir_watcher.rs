@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::helpers::error::SyncError;
+
+pub const DEFAULT_DEBOUNCE_MS: u64 = 500;
+// a batch is flushed after this many debounce windows even if events keep coming
+const MAX_BATCH_WINDOWS: u32 = 10;
+
+// watches a directory tree and groups bursts of filesystem events into batches of changed paths
+pub struct DirWatcher {
+    root: PathBuf,
+    // dropping the watcher stops the events, so it is kept alive here
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    debounce: Duration,
+}
+
+impl DirWatcher {
+    pub fn new(root: &Path, debounce: Duration) -> Result<Self, SyncError> {
+        // the events carry absolute paths, so the root has to be absolute too to strip it
+        let root = root.canonicalize().map_err(|e| SyncError::io(root, e))?;
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(|e| watch_error(&root, e))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| watch_error(&root, e))?;
+        Ok(DirWatcher {
+            root,
+            _watcher: watcher,
+            events,
+            debounce,
+        })
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    // waits up to `timeout` for something to change, then waits for the burst to settle and returns the changed paths relative to the root
+    // an empty string in the batch means the events overflowed and the whole tree has to be rescanned
+    pub fn next_batch_timeout(&self, timeout: Duration) -> Result<Option<Vec<String>>, SyncError> {
+        match self.events.recv_timeout(timeout) {
+            Ok(first) => self.collect_batch(first).map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(watch_error(&self.root, "the watcher stopped")),
+        }
+    }
+
+    fn collect_batch(&self, first: notify::Result<Event>) -> Result<Vec<String>, SyncError> {
+        let mut batch = BTreeSet::new();
+        self.add_event(&mut batch, first);
+        let started = Instant::now();
+        while started.elapsed() < self.debounce * MAX_BATCH_WINDOWS {
+            match self.events.recv_timeout(self.debounce) {
+                Ok(event) => self.add_event(&mut batch, event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(watch_error(&self.root, "the watcher stopped")),
+            }
+        }
+        Ok(batch.into_iter().collect())
+    }
+
+    fn add_event(&self, batch: &mut BTreeSet<String>, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            // events were lost, fall back to a full rescan
+            Err(_) => {
+                batch.insert(String::new());
+                return;
+            }
+        };
+        if event.need_rescan() {
+            batch.insert(String::new());
+            return;
+        }
+        match event.kind {
+            // reads (including our own while hashing) do not change anything
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => {}
+            EventKind::Access(_) | EventKind::Other => return,
+            _ => {}
+        }
+        for path in &event.paths {
+            if let Some(relative_path) = self.get_relative_path(path) {
+                batch.insert(relative_path);
+            }
+        }
+    }
+
+    // None for paths outside the root and for the .hard_sync_cli state written by the sync itself
+    fn get_relative_path(&self, path: &Path) -> Option<String> {
+        let relative_path = path.strip_prefix(&self.root).ok()?;
+        if let Some(Component::Normal(first)) = relative_path.components().next() {
+            if first == ".hard_sync_cli" {
+                return None;
+            }
+        }
+        let relative_path = relative_path.to_str()?;
+        match relative_path.is_empty() {
+            true => None,
+            false => Some(relative_path.to_string()),
+        }
+    }
+}
+
+fn watch_error(root: &Path, message: impl std::fmt::Display) -> SyncError {
+    SyncError::Watch {
+        path: root.to_string_lossy().to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,268 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use sysinfo::Disks;
+
+// a mounted disk as seen by hsync
+#[derive(Debug, Clone)]
+pub struct DriveInfo {
+    name: String,
+    label: Option<String>,
+    uuid: Option<String>,
+    mount_point: PathBuf,
+    file_system: String,
+    total_space: u64,
+    available_space: u64,
+    removable: bool,
+}
+
+impl DriveInfo {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        name: &str,
+        label: Option<String>,
+        uuid: Option<String>,
+        mount_point: &Path,
+        file_system: &str,
+        total_space: u64,
+        available_space: u64,
+        removable: bool,
+    ) -> Self {
+        DriveInfo {
+            name: name.to_string(),
+            label,
+            uuid,
+            mount_point: mount_point.to_path_buf(),
+            file_system: file_system.to_string(),
+            total_space,
+            available_space,
+            removable,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn get_uuid(&self) -> Option<&str> {
+        self.uuid.as_deref()
+    }
+
+    pub fn get_mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    pub fn get_file_system(&self) -> &str {
+        &self.file_system
+    }
+
+    pub fn get_total_space(&self) -> u64 {
+        self.total_space
+    }
+
+    pub fn get_available_space(&self) -> u64 {
+        self.available_space
+    }
+
+    pub fn is_removable(&self) -> bool {
+        self.removable
+    }
+
+    // labels and uuids are both compared case insensitively, uuids are printed in either case depending on the file system
+    pub fn has_label(&self, label: &str) -> bool {
+        self.label.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(label))
+    }
+
+    pub fn has_uuid(&self, uuid: &str) -> bool {
+        self.uuid.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(uuid))
+    }
+}
+
+// where the list of mounted disks comes from, so the lookup can run against a fake list
+pub trait DiskProvider {
+    fn list_disks(&self) -> Vec<DriveInfo>;
+}
+
+// the real disks from sysinfo, with labels and uuids from /dev/disk/by-label and /dev/disk/by-uuid
+pub struct SystemDiskProvider;
+
+impl DiskProvider for SystemDiskProvider {
+    fn list_disks(&self) -> Vec<DriveInfo> {
+        let labels = read_device_links(Path::new("/dev/disk/by-label"));
+        let uuids = read_device_links(Path::new("/dev/disk/by-uuid"));
+        Disks::new_with_refreshed_list()
+            .list()
+            .iter()
+            .map(|disk| {
+                let name = disk.name().to_string_lossy().to_string();
+                let device = fs::canonicalize(&name).unwrap_or_else(|_| PathBuf::from(&name));
+                // outside linux the disk name is already the volume name
+                let label = labels.get(&device).cloned().or_else(|| match name.starts_with("/dev/") || name.is_empty() {
+                    true => None,
+                    false => Some(name.clone()),
+                });
+                DriveInfo::new(
+                    &name,
+                    label,
+                    uuids.get(&device).cloned(),
+                    disk.mount_point(),
+                    &disk.file_system().to_string_lossy(),
+                    disk.total_space(),
+                    disk.available_space(),
+                    disk.is_removable(),
+                )
+            })
+            .collect()
+    }
+}
+
+pub fn list_removable_drives(provider: &dyn DiskProvider) -> Vec<DriveInfo> {
+    provider.list_disks().into_iter().filter(|d| d.is_removable()).collect()
+}
+
+// find a mounted drive by its label or uuid, wherever it is mounted
+// a uuid names a single file system so it wins over labels, a label shared by several drives is refused
+pub fn find_drive(provider: &dyn DiskProvider, label_or_uuid: &str) -> Result<DriveInfo, String> {
+    let drives = provider.list_disks();
+    let mut matches: Vec<&DriveInfo> = drives.iter().filter(|d| d.has_uuid(label_or_uuid)).collect();
+    if matches.is_empty() {
+        matches = drives.iter().filter(|d| d.has_label(label_or_uuid)).collect();
+    }
+    match matches.as_slice() {
+        [] => Err(format!("No mounted drive with label or UUID {}", label_or_uuid)),
+        [drive] => Ok((*drive).clone()),
+        // the same file system mounted in more than one place
+        [first, rest @ ..] if first.uuid.is_some() && rest.iter().all(|d| d.uuid == first.uuid) => Ok((*first).clone()),
+        _ => {
+            let found: Vec<String> = matches
+                .iter()
+                .map(|d| format!("{} ({})", d.get_mount_point().display(), d.get_uuid().unwrap_or("no UUID")))
+                .collect();
+            Err(format!(
+                "{} mounted drives have the label {}, pass the UUID of the one to sync to: {}",
+                matches.len(), label_or_uuid, found.join(", ")
+            ))
+        }
+    }
+}
+
+// map each device a /dev/disk/by-* link points to onto the (unescaped) link name
+fn read_device_links(dir: &Path) -> HashMap<PathBuf, String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let device = fs::canonicalize(entry.path()).ok()?;
+            let name = entry.file_name().to_str()?.to_string();
+            Some((device, unescape_udev(&name)))
+        })
+        .collect()
+}
+
+// udev escapes spaces and other special characters in link names as \xNN
+fn unescape_udev(name: &str) -> String {
+    let mut bytes = Vec::with_capacity(name.len());
+    let raw = name.as_bytes();
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'\\' && raw.get(i + 1) == Some(&b'x') && i + 3 < raw.len() {
+            let hex = std::str::from_utf8(&raw[i + 2..i + 4]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                bytes.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        bytes.push(raw[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeDisks(Vec<DriveInfo>);
+
+    impl DiskProvider for FakeDisks {
+        fn list_disks(&self) -> Vec<DriveInfo> {
+            self.0.clone()
+        }
+    }
+
+    fn drive(label: Option<&str>, uuid: Option<&str>, mount_point: &str, removable: bool) -> DriveInfo {
+        DriveInfo::new("/dev/sdx1", label.map(String::from), uuid.map(String::from), Path::new(mount_point), "exfat", 100, 50, removable)
+    }
+
+    fn disks() -> FakeDisks {
+        FakeDisks(vec![
+            drive(None, Some("0b1e-77aa"), "/", false),
+            drive(Some("BACKUP01"), Some("5E2A-91C4"), "/media/usb", true),
+            drive(Some("Photos"), None, "/media/photos", true),
+        ])
+    }
+
+    #[test]
+    fn finds_a_drive_by_label() {
+        let found = find_drive(&disks(), "backup01").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/usb"));
+        let found = find_drive(&disks(), "Photos").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/photos"));
+    }
+
+    #[test]
+    fn finds_a_drive_by_uuid() {
+        let found = find_drive(&disks(), "5e2a-91c4").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/usb"));
+        let found = find_drive(&disks(), "0b1e-77aa").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/"));
+    }
+
+    #[test]
+    fn reports_a_missing_drive() {
+        let error = find_drive(&disks(), "BACKUP02").unwrap_err();
+        assert_eq!(error, "No mounted drive with label or UUID BACKUP02");
+        assert!(find_drive(&FakeDisks(Vec::new()), "BACKUP01").is_err());
+    }
+
+    #[test]
+    fn refuses_a_label_shared_by_several_drives() {
+        let mut disks = disks();
+        disks.0.push(drive(Some("Backup01"), Some("1111-2222"), "/media/usb2", true));
+        let error = find_drive(&disks, "BACKUP01").unwrap_err();
+        assert!(error.starts_with("2 mounted drives have the label BACKUP01"), "{}", error);
+        assert!(error.contains("/media/usb (5E2A-91C4)") && error.contains("/media/usb2 (1111-2222)"), "{}", error);
+        // the uuid still picks one of them
+        let found = find_drive(&disks, "1111-2222").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/usb2"));
+    }
+
+    #[test]
+    fn accepts_one_file_system_mounted_twice() {
+        let mut disks = disks();
+        disks.0.push(drive(Some("BACKUP01"), Some("5E2A-91C4"), "/mnt/backup", true));
+        let found = find_drive(&disks, "BACKUP01").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/usb"));
+    }
+
+    #[test]
+    fn a_uuid_wins_over_a_label() {
+        let mut disks = disks();
+        disks.0.push(drive(Some("5E2A-91C4"), None, "/media/odd", true));
+        let found = find_drive(&disks, "5E2A-91C4").unwrap();
+        assert_eq!(found.get_mount_point(), Path::new("/media/usb"));
+    }
+
+    #[test]
+    fn lists_only_removable_drives() {
+        let drives = list_removable_drives(&disks());
+        let mounts: Vec<&Path> = drives.iter().map(|d| d.get_mount_point()).collect();
+        assert_eq!(mounts, [Path::new("/media/usb"), Path::new("/media/photos")]);
+    }
+}
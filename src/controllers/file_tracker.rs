@@ -1,36 +1,163 @@
 use std::{fmt, fs};
-use std::path::Path;
-use std::time::UNIX_EPOCH;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::de;
 use serde::{de::Visitor, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Sha256, Digest};  // You may need to add `sha2` crate for hashing
+
+use super::tracker_config::{LinkPolicy, PreserveMetadata};
+
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+// every directory has the same hash, so directories only differ by whether they exist and by their attributes
+pub const DIR_HASH: &str = "directory";
+
+// timestamps before 1970, which some filesystems report for broken files, count as the epoch
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+// permission bits, uid and gid, all 0 on platforms without them
+#[cfg(unix)]
+fn unix_attributes(metadata: &fs::Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode() & 0o7777, metadata.uid(), metadata.gid())
+}
+
+#[cfg(not(unix))]
+fn unix_attributes(_metadata: &fs::Metadata) -> (u32, u32, u32) {
+    (0, 0, 0)
+}
+
+// device and inode, so files that are hard links to the same data can be grouped, None where they can not be told apart
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// the metadata of the file at `path`, following a link, and its inode unless it was reached through a link
+// a followed link is not a hard link of the file it points to
+fn file_metadata(path: &Path) -> std::io::Result<(fs::Metadata, Option<(u64, u64)>)> {
+    let metadata = fs::symlink_metadata(path)?;
+    match metadata.file_type().is_symlink() {
+        true => Ok((fs::metadata(path)?, None)),
+        false => {
+            let inode = inode(&metadata);
+            Ok((metadata, inode))
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileTracker {
     path: String,
     size: u64,
-    content: Vec<u8>,
     last_modified: u64,
+    // sub second part of the mtime, so a rewrite within the same second is not mistaken for an unchanged file
+    last_modified_nanos: u32,
     created: u64,
     last_accessed: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
     extension: String,
+    // set when the path is a symbolic link synced as a link, the hashes are then of this target
+    link_target: Option<String>,
+    // a directory, tracked so empty ones are synced and its attributes can be kept
+    directory: bool,
+    // device and inode, read from disk on every scan to find the files sharing their data, never saved
+    inode: Option<(u64, u64)>,
+    // the first file (by path) of the hard link group this file belongs to, None for that first file itself
+    hard_link: Option<String>,
     last_file_hash: String,
     current_file_hash: String,
 }
 
 impl FileTracker {
     pub fn new(file_path: &str) -> std::io::Result<Self> {
+        Self::new_with_cache(file_path, None)
+    }
+
+    // the link at `file_path` itself rather than what it points to, with `links` set to copy
+    // any other path, and any other policy, reads the file the way `new` does
+    pub fn open(file_path: &str, links: LinkPolicy) -> std::io::Result<Self> {
+        match links == LinkPolicy::Copy && fs::symlink_metadata(file_path)?.file_type().is_symlink() {
+            true => Self::new_link(file_path),
+            false => Self::new(file_path),
+        }
+    }
+
+    // track a symbolic link by its target, the target itself is never read
+    pub fn new_link(file_path: &str) -> std::io::Result<Self> {
         let path = Path::new(file_path);
-        
-        // Get file metadata
-        let metadata = fs::metadata(path)?;
+        let metadata = fs::symlink_metadata(path)?;
+        let link_target = read_link_target(path)?;
+        let modified = since_epoch(metadata.modified()?);
+        let (mode, uid, gid) = unix_attributes(&metadata);
+        let hash = Self::hash_link(Path::new(&link_target));
+        Ok(FileTracker {
+            path: file_path.to_string(),
+            size: link_target.len() as u64,
+            last_modified: modified.as_secs(),
+            last_modified_nanos: modified.subsec_nanos(),
+            created: since_epoch(metadata.created().unwrap_or(UNIX_EPOCH)).as_secs(),
+            last_accessed: since_epoch(metadata.accessed()?).as_secs(),
+            mode,
+            uid,
+            gid,
+            extension: String::new(),
+            link_target: Some(link_target),
+            directory: false,
+            inode: None,
+            hard_link: None,
+            last_file_hash: hash.clone(),
+            current_file_hash: hash,
+        })
+    }
 
-        // Read file content
-        let content = fs::read(path)?;
+    // track a directory by its attributes alone
+    pub fn new_dir(dir_path: &str) -> std::io::Result<Self> {
+        let metadata = fs::metadata(dir_path)?;
+        let modified = since_epoch(metadata.modified()?);
+        let (mode, uid, gid) = unix_attributes(&metadata);
+        Ok(FileTracker {
+            path: dir_path.to_string(),
+            size: 0,
+            last_modified: modified.as_secs(),
+            last_modified_nanos: modified.subsec_nanos(),
+            created: since_epoch(metadata.created().unwrap_or(UNIX_EPOCH)).as_secs(),
+            last_accessed: since_epoch(metadata.accessed()?).as_secs(),
+            mode,
+            uid,
+            gid,
+            extension: String::new(),
+            link_target: None,
+            directory: true,
+            inode: None,
+            hard_link: None,
+            last_file_hash: DIR_HASH.to_string(),
+            current_file_hash: DIR_HASH.to_string(),
+        })
+    }
 
-        let last_modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let created = metadata.created().unwrap_or(UNIX_EPOCH).duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let last_accessed = metadata.accessed()?.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // like `new`, but reuses the hashes of `cached` (the entry from tracker.json) when the size and mtime still match
+    pub fn new_with_cache(file_path: &str, cached: Option<&FileTracker>) -> std::io::Result<Self> {
+        let path = Path::new(file_path);
+        
+        // Get file metadata
+        let (metadata, inode) = file_metadata(path)?;
 
+        let modified = since_epoch(metadata.modified()?);
+        let (last_modified, last_modified_nanos) = (modified.as_secs(), modified.subsec_nanos());
+        let created = since_epoch(metadata.created().unwrap_or(UNIX_EPOCH)).as_secs();
+        let last_accessed = since_epoch(metadata.accessed()?).as_secs();
+        let (mode, uid, gid) = unix_attributes(&metadata);
 
         // File extension
         let extension = path.extension()
@@ -38,18 +165,36 @@ impl FileTracker {
             .unwrap_or("")
             .to_string();
 
-        // File hashes
-        let current_file_hash = Self::calculate_hash(&content);
-        let last_file_hash = current_file_hash.clone();  // Assume it's the same initially
+        // File hashes, the content is streamed through the hasher and never kept in memory
+        let (last_file_hash, current_file_hash) = match cached {
+            Some(cached) if cached.link_target.is_none()
+                && !cached.directory
+                && cached.size == metadata.len()
+                && cached.last_modified == last_modified
+                && cached.last_modified_nanos == last_modified_nanos => {
+                (cached.last_file_hash.clone(), cached.current_file_hash.clone())
+            }
+            _ => {
+                let current_file_hash = Self::hash_file(path)?;
+                (current_file_hash.clone(), current_file_hash)  // Assume it's the same initially
+            }
+        };
 
         Ok(FileTracker {
             path: file_path.to_string(),
             size: metadata.len(),
-            content,
             last_modified,
+            last_modified_nanos,
             created,
             last_accessed,
+            mode,
+            uid,
+            gid,
             extension,
+            link_target: None,
+            directory: false,
+            inode,
+            hard_link: None,
             last_file_hash,
             current_file_hash,
         })
@@ -61,10 +206,77 @@ impl FileTracker {
         format!("{:x}", hasher.finalize())  // Return as a hex string
     }
 
-    pub fn update_hash(&mut self) {
-        self.last_file_hash = self.current_file_hash.clone();
-        self.current_file_hash = Self::calculate_hash(&self.content);
+    // hash a file in fixed size chunks so memory use does not grow with the file size
+    pub fn hash_file(path: &Path) -> std::io::Result<String> {
+        let mut reader = BufReader::with_capacity(HASH_BUFFER_SIZE, fs::File::open(path)?);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    // the hash of a link pointing at `target`, prefixed so it never equals the hash of a file
+    pub fn hash_link(target: &Path) -> String {
+        format!("link:{}", Self::calculate_hash(target.to_string_lossy().as_bytes()))
+    }
+
+    pub fn update_hash(&mut self) -> std::io::Result<()> {
+        let current_file_hash = Self::hash_file(Path::new(&self.path))?;
+        self.last_file_hash = std::mem::replace(&mut self.current_file_hash, current_file_hash);
+        Ok(())
+    }
+
+    // re-read the metadata from disk, rehashing (and rotating the hashes) only when the size or mtime changed
+    // returns true when the content changed
+    pub fn refresh(&mut self) -> std::io::Result<bool> {
+        if self.link_target.is_some() {
+            return self.refresh_link();
+        }
+        // the content of a directory is tracked through its entries, only its attributes are read here
+        if self.directory {
+            *self = Self::new_dir(&self.path)?;
+            return Ok(false);
+        }
+        let (metadata, inode) = file_metadata(Path::new(&self.path))?;
+        let modified = since_epoch(metadata.modified()?);
+        self.last_accessed = since_epoch(metadata.accessed()?).as_secs();
+        (self.mode, self.uid, self.gid) = unix_attributes(&metadata);
+        self.inode = inode;
+        if metadata.len() == self.size && modified.as_secs() == self.last_modified && modified.subsec_nanos() == self.last_modified_nanos {
+            return Ok(false);
+        }
+        self.size = metadata.len();
+        self.last_modified = modified.as_secs();
+        self.last_modified_nanos = modified.subsec_nanos();
+        self.update_hash()?;
+        Ok(self.last_file_hash != self.current_file_hash)
     }
+
+    // a link changes when it is pointed somewhere else, the target is read again every time since it is short
+    fn refresh_link(&mut self) -> std::io::Result<bool> {
+        let mut refreshed = Self::new_link(&self.path)?;
+        let changed = refreshed.current_file_hash != self.current_file_hash;
+        refreshed.last_file_hash = match changed {
+            true => std::mem::take(&mut self.current_file_hash),
+            false => std::mem::take(&mut self.last_file_hash),
+        };
+        *self = refreshed;
+        Ok(changed)
+    }
+}
+
+// the target of the link at `path`, it has to be valid utf-8 to be stored in tracker.json
+fn read_link_target(path: &Path) -> std::io::Result<String> {
+    let target: PathBuf = fs::read_link(path)?;
+    target.into_os_string().into_string().map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "the link target is not valid UTF-8")
+    })
 }
 
 
@@ -77,15 +289,59 @@ impl FileTracker {
 
     pub fn get_relative_path(&self, base_path: &Path) -> &str {
         let path = Path::new(&self.path);
-        path.strip_prefix(base_path).unwrap().to_str().unwrap()
+        // files are always tracked under their root, the full path is only returned for a mismatched root
+        path.strip_prefix(base_path).ok().and_then(|p| p.to_str()).unwrap_or(&self.path)
     }
 
     pub fn get_size(&self) -> u64 {
         self.size
     }
 
-    pub fn get_content(&self) -> &[u8] {
-        &self.content
+    pub fn is_link(&self) -> bool {
+        self.link_target.is_some()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.directory
+    }
+
+    pub fn get_link_target(&self) -> Option<&str> {
+        self.link_target.as_deref()
+    }
+
+    pub fn get_inode(&self) -> Option<(u64, u64)> {
+        self.inode
+    }
+
+    pub fn get_hard_link(&self) -> Option<&str> {
+        self.hard_link.as_deref()
+    }
+
+    // set by the directory once it knows every file of the group
+    pub(crate) fn set_hard_link(&mut self, hard_link: Option<String>) {
+        self.hard_link = hard_link;
+    }
+
+    // the content is not kept on the tracker, it is read from disk when needed
+    pub fn read_content(&self) -> std::io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+
+    // same modification time down to the nanosecond
+    pub fn has_same_mtime(&self, other: &FileTracker) -> bool {
+        self.last_modified == other.last_modified && self.last_modified_nanos == other.last_modified_nanos
+    }
+
+    // same modification time, permission bits and owner
+    pub fn has_same_attributes(&self, other: &FileTracker) -> bool {
+        self.has_same_mtime(other) && self.mode == other.mode && self.uid == other.uid && self.gid == other.gid
+    }
+
+    // whether the attributes `preserve` keeps match, so only the metadata has to be synced when the content is the same
+    pub fn has_same_metadata(&self, other: &FileTracker, preserve: PreserveMetadata) -> bool {
+        (!preserve.get_times() || self.has_same_mtime(other))
+            && (!preserve.get_permissions() || self.mode == other.mode)
+            && (!preserve.get_owner() || (self.uid == other.uid && self.gid == other.gid))
     }
 
     pub fn get_last_modified(&self) -> u64 {
@@ -100,6 +356,18 @@ impl FileTracker {
         self.last_accessed
     }
 
+    pub fn get_mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn get_uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn get_gid(&self) -> u32 {
+        self.gid
+    }
+
     pub fn get_extension(&self) -> &str {
         &self.extension
     }
@@ -122,7 +390,7 @@ impl Serialize for FileTracker {
         S: Serializer,
     {
         // Start a struct serialization with 3 fields
-        let mut state = serializer.serialize_struct("FileTracker", 8)?;
+        let mut state = serializer.serialize_struct("FileTracker", 15)?;
 
         // Customize the serialized name and value for each field
         // Customize each field
@@ -130,9 +398,16 @@ impl Serialize for FileTracker {
         state.serialize_field("file_size_bytes", &self.size)?;
         // You might want to convert timestamps to human-readable format, but here we use them as-is
         state.serialize_field("last_modified_timestamp", &self.last_modified)?;
+        state.serialize_field("last_modified_nanos", &self.last_modified_nanos)?;
         state.serialize_field("created_timestamp", &self.created)?;
         state.serialize_field("last_accessed_timestamp", &self.last_accessed)?;
+        state.serialize_field("mode", &self.mode)?;
+        state.serialize_field("uid", &self.uid)?;
+        state.serialize_field("gid", &self.gid)?;
         state.serialize_field("file_extension", &self.extension)?;
+        state.serialize_field("link_target", &self.link_target)?;
+        state.serialize_field("directory", &self.directory)?;
+        state.serialize_field("hard_link", &self.hard_link)?;
         state.serialize_field("previous_file_hash", &self.last_file_hash)?;
         state.serialize_field("current_file_hash", &self.current_file_hash)?;
 
@@ -164,9 +439,16 @@ impl<'de> Deserialize<'de> for FileTracker {
                 let mut path = None;
                 let mut size = None;
                 let mut last_modified = None;
+                let mut last_modified_nanos = None;
                 let mut created = None;
                 let mut last_accessed = None;
+                let mut mode = None;
+                let mut uid = None;
+                let mut gid = None;
                 let mut extension = None;
+                let mut link_target: Option<Option<String>> = None;
+                let mut directory = None;
+                let mut hard_link: Option<Option<String>> = None;
                 let mut last_file_hash = None;
                 let mut current_file_hash = None;
 
@@ -190,6 +472,12 @@ impl<'de> Deserialize<'de> for FileTracker {
                             }
                             last_modified = Some(map.next_value()?);
                         }
+                        "last_modified_nanos" => {
+                            if last_modified_nanos.is_some() {
+                                return Err(de::Error::duplicate_field("last_modified_nanos"));
+                            }
+                            last_modified_nanos = Some(map.next_value()?);
+                        }
                         "created_timestamp" => {
                             if created.is_some() {
                                 return Err(de::Error::duplicate_field("created_timestamp"));
@@ -202,12 +490,48 @@ impl<'de> Deserialize<'de> for FileTracker {
                             }
                             last_accessed = Some(map.next_value()?);
                         }
+                        "mode" => {
+                            if mode.is_some() {
+                                return Err(de::Error::duplicate_field("mode"));
+                            }
+                            mode = Some(map.next_value()?);
+                        }
+                        "uid" => {
+                            if uid.is_some() {
+                                return Err(de::Error::duplicate_field("uid"));
+                            }
+                            uid = Some(map.next_value()?);
+                        }
+                        "gid" => {
+                            if gid.is_some() {
+                                return Err(de::Error::duplicate_field("gid"));
+                            }
+                            gid = Some(map.next_value()?);
+                        }
                         "file_extension" => {
                             if extension.is_some() {
                                 return Err(de::Error::duplicate_field("file_extension"));
                             }
                             extension = Some(map.next_value()?);
                         }
+                        "link_target" => {
+                            if link_target.is_some() {
+                                return Err(de::Error::duplicate_field("link_target"));
+                            }
+                            link_target = Some(map.next_value()?);
+                        }
+                        "directory" => {
+                            if directory.is_some() {
+                                return Err(de::Error::duplicate_field("directory"));
+                            }
+                            directory = Some(map.next_value()?);
+                        }
+                        "hard_link" => {
+                            if hard_link.is_some() {
+                                return Err(de::Error::duplicate_field("hard_link"));
+                            }
+                            hard_link = Some(map.next_value()?);
+                        }
                         "previous_file_hash" => {
                             if last_file_hash.is_some() {
                                 return Err(de::Error::duplicate_field("previous_file_hash"));
@@ -231,20 +555,37 @@ impl<'de> Deserialize<'de> for FileTracker {
                 let path = path.ok_or_else(|| de::Error::missing_field("file_path"))?;
                 let size = size.ok_or_else(|| de::Error::missing_field("file_size_bytes"))?;
                 let last_modified = last_modified.ok_or_else(|| de::Error::missing_field("last_modified_timestamp"))?;
+                // trackers written before the nanos were recorded get rehashed once
+                let last_modified_nanos = last_modified_nanos.unwrap_or(0);
                 let created = created.ok_or_else(|| de::Error::missing_field("created_timestamp"))?;
                 let last_accessed = last_accessed.ok_or_else(|| de::Error::missing_field("last_accessed_timestamp"))?;
+                // trackers written before the attributes were recorded, the next scan reads them from disk
+                let (mode, uid, gid) = (mode.unwrap_or(0), uid.unwrap_or(0), gid.unwrap_or(0));
                 let extension = extension.ok_or_else(|| de::Error::missing_field("file_extension"))?;
+                // trackers written before links were synced only hold files
+                let link_target = link_target.flatten();
+                // and before directories were tracked
+                let directory = directory.unwrap_or(false);
+                // and before hard links were, the next scan groups them
+                let hard_link = hard_link.flatten();
                 let last_file_hash = last_file_hash.ok_or_else(|| de::Error::missing_field("previous_file_hash"))?;
                 let current_file_hash = current_file_hash.ok_or_else(|| de::Error::missing_field("current_file_hash"))?;
 
                 Ok(FileTracker {
                     path,
                     size,
-                    content: vec![], // We can default it to an empty vector if content is not part of the deserialization
                     last_modified,
+                    last_modified_nanos,
                     created,
                     last_accessed,
+                    mode,
+                    uid,
+                    gid,
                     extension,
+                    link_target,
+                    directory,
+                    inode: None,
+                    hard_link,
                     last_file_hash,
                     current_file_hash,
                 })
@@ -0,0 +1,282 @@
+use regex::Regex;
+
+// a single compiled hard_sync.ignore / --exclude pattern
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+// matches relative paths against patterns with .gitignore semantics
+// patterns are compiled once when added, the last matching pattern wins and `!` re-includes a path
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreMatcher {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreMatcher {
+    pub fn new() -> Self {
+        IgnoreMatcher { rules: Vec::new() }
+    }
+
+    // add one line of an ignore file, blank lines and comments are skipped
+    pub fn add(&mut self, line: &str) {
+        if let Some(rule) = IgnoreRule::parse(line) {
+            self.rules.push(rule);
+        }
+    }
+
+    // `path` is relative to the synced directory
+    // like git, a path inside an ignored directory stays ignored even if a later pattern re-includes it
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = normalize(path);
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        for end in 1..parts.len() {
+            if self.matches(&parts[..end].join("/"), true) {
+                return true;
+            }
+        }
+        self.matches(&parts.join("/"), is_dir)
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            if rule.regex.is_match(path) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<IgnoreRule> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, mut pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.as_str()),
+        };
+        let dir_only = pattern.ends_with('/') && !pattern.ends_with("\\/");
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        // a slash anywhere but the end anchors the pattern to the root, otherwise it matches at any depth
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let body = glob_to_regex(pattern);
+        let regex = match anchored {
+            true => format!("^{}$", body),
+            false => format!("^(?:.*/)?{}$", body),
+        };
+        // the translation escapes everything it does not understand, so the regex is always valid
+        Regex::new(&regex).ok().map(|regex| IgnoreRule { regex, negated, dir_only })
+    }
+}
+
+// translate a gitignore glob into a regex body
+fn glob_to_regex(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let at_end = i + 2 == chars.len();
+                if at_start && chars.get(i + 2) == Some(&'/') {
+                    // `**/` matches zero or more directories
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else if at_start && at_end {
+                    // trailing `/**` matches everything inside
+                    regex.push_str(".*");
+                    i += 2;
+                } else {
+                    regex.push_str("[^/]*");
+                    i += 2;
+                }
+            }
+            '*' => {
+                regex.push_str("[^/]*");
+                i += 1;
+            }
+            '?' => {
+                regex.push_str("[^/]");
+                i += 1;
+            }
+            '[' => match parse_class(&chars[i..]) {
+                Some((class, len)) => {
+                    regex.push_str(&class);
+                    i += len;
+                }
+                None => {
+                    regex.push_str(r"\[");
+                    i += 1;
+                }
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            c => {
+                regex.push_str(&regex::escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    regex
+}
+
+// `[abc]`, `[a-z]` and `[!abc]` character classes, returns the regex and how many chars were used
+fn parse_class(chars: &[char]) -> Option<(String, usize)> {
+    let mut class = String::from("[");
+    let mut i = 1;
+    if matches!(chars.get(i), Some('!') | Some('^')) {
+        class.push('^');
+        i += 1;
+    }
+    let start = i;
+    while i < chars.len() {
+        match chars[i] {
+            ']' if i > start => {
+                class.push(']');
+                return Some((class, i + 1));
+            }
+            '\\' if i + 1 < chars.len() => {
+                class.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            '/' => return None,
+            c @ ('[' | ']' | '&' | '~' | '^') => {
+                class.push('\\');
+                class.push(c);
+                i += 1;
+            }
+            c => {
+                class.push(c);
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+// trailing spaces are dropped unless escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> String {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut end = line.len();
+    while end > 0 && line.as_bytes()[end - 1] == b' ' {
+        if end >= 2 && line.as_bytes()[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    line[..end].to_string()
+}
+
+fn normalize(path: &str) -> String {
+    match std::path::MAIN_SEPARATOR {
+        '/' => path.to_string(),
+        separator => path.replace(separator, "/"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreMatcher;
+    use crate::controllers::dir_tracker::DirTracker;
+
+    fn matcher(lines: &[&str]) -> IgnoreMatcher {
+        let mut matcher = IgnoreMatcher::new();
+        for line in lines {
+            matcher.add(line);
+        }
+        matcher
+    }
+
+    #[test]
+    fn negation_re_includes_a_path() {
+        let matcher = matcher(&["*.log", "!keep.log"]);
+        assert!(matcher.is_ignored("debug.log", false));
+        assert!(matcher.is_ignored("logs/debug.log", false));
+        assert!(!matcher.is_ignored("keep.log", false));
+        assert!(!matcher.is_ignored("logs/keep.log", false));
+    }
+
+    #[test]
+    fn negation_does_not_re_include_inside_an_ignored_directory() {
+        let matcher = matcher(&["build/", "!build/keep.txt"]);
+        assert!(matcher.is_ignored("build/keep.txt", false));
+    }
+
+    #[test]
+    fn anchored_patterns_only_match_at_the_root() {
+        let matcher = matcher(&["/todo.txt", "docs/*.md"]);
+        assert!(matcher.is_ignored("todo.txt", false));
+        assert!(!matcher.is_ignored("notes/todo.txt", false));
+        assert!(matcher.is_ignored("docs/readme.md", false));
+        assert!(!matcher.is_ignored("docs/api/readme.md", false));
+        assert!(!matcher.is_ignored("src/docs/readme.md", false));
+    }
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        let matcher = matcher(&["**/cache", "assets/**", "a/**/z.txt"]);
+        assert!(matcher.is_ignored("cache", true));
+        assert!(matcher.is_ignored("one/two/cache", true));
+        assert!(matcher.is_ignored("assets/img/logo.png", false));
+        assert!(!matcher.is_ignored("assets", true));
+        assert!(matcher.is_ignored("a/z.txt", false));
+        assert!(matcher.is_ignored("a/b/c/z.txt", false));
+        assert!(!matcher.is_ignored("b/z.txt", false));
+    }
+
+    #[test]
+    fn directory_only_patterns_skip_files() {
+        let matcher = matcher(&["target/"]);
+        assert!(matcher.is_ignored("target", true));
+        assert!(!matcher.is_ignored("target", false));
+        assert!(matcher.is_ignored("target/debug/hsync", false));
+        assert!(matcher.is_ignored("crates/x/target/out.o", false));
+    }
+
+    #[test]
+    fn comments_blank_lines_and_escapes() {
+        let matcher = matcher(&["# a comment", "", "\\#notes", "name\\ "]);
+        assert!(matcher.is_ignored("#notes", false));
+        assert!(matcher.is_ignored("name ", false));
+        assert!(!matcher.is_ignored("# a comment", false));
+    }
+
+    #[test]
+    fn ignored_directories_are_not_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/pkg")).unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("node_modules/pkg/index.js"), "x").unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("hard_sync.ignore"), "node_modules/\n").unwrap();
+
+        let mut tracker = DirTracker::new(dir.path()).unwrap();
+        tracker.import_files_from_directory(true);
+        assert!(tracker.has_file("src/main.rs"));
+        assert!(!tracker.has_file("node_modules"));
+        assert!(!tracker.has_file("node_modules/pkg/index.js"));
+        assert!(!tracker.has_file("hard_sync.ignore"));
+        // the ignored directory is reported once, the ignore file is not reported at all
+        let ignored: Vec<(&str, bool)> = tracker.get_ignored().iter().map(|e| (e.get_path(), e.is_dir())).collect();
+        assert_eq!(ignored, [("node_modules", true)]);
+    }
+}
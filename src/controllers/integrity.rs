@@ -0,0 +1,142 @@
+use std::{fmt, path::Path};
+
+use super::dir_tracker::DirTracker;
+use crate::helpers::error::SyncError;
+
+// how a file differs from the state saved in tracker.json by the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityStatus {
+    // tracked but gone from the disk
+    Missing,
+    // on the disk but never synced
+    New,
+    // changed with a new modification time, an edit made after the last sync
+    Modified,
+    // the content changed but the modification time did not, the data rotted or was damaged
+    Bitrot,
+}
+
+impl IntegrityStatus {
+    // a scrub fails on everything except regular edits
+    pub fn is_failure(&self) -> bool {
+        *self != IntegrityStatus::Modified
+    }
+}
+
+impl fmt::Display for IntegrityStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IntegrityStatus::Missing => "Missing",
+            IntegrityStatus::New => "New",
+            IntegrityStatus::Modified => "Modified",
+            IntegrityStatus::Bitrot => "Bitrot",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegrityIssue {
+    path: String,
+    status: IntegrityStatus,
+    // None when the file does not exist on that side
+    expected_hash: Option<String>,
+    actual_hash: Option<String>,
+}
+
+impl IntegrityIssue {
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_status(&self) -> IntegrityStatus {
+        self.status
+    }
+
+    pub fn get_expected_hash(&self) -> Option<&str> {
+        self.expected_hash.as_deref()
+    }
+
+    pub fn get_actual_hash(&self) -> Option<&str> {
+        self.actual_hash.as_deref()
+    }
+}
+
+// the result of rehashing a synced directory
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    checked: usize,
+    issues: Vec<IntegrityIssue>,
+    // files that could not be read, so they could not be checked
+    errors: Vec<SyncError>,
+}
+
+impl IntegrityReport {
+    pub fn get_checked(&self) -> usize {
+        self.checked
+    }
+
+    pub fn get_issues(&self) -> &[IntegrityIssue] {
+        &self.issues
+    }
+
+    pub fn get_errors(&self) -> &[SyncError] {
+        &self.errors
+    }
+
+    pub fn count(&self, status: IntegrityStatus) -> usize {
+        self.issues.iter().filter(|issue| issue.status == status).count()
+    }
+
+    // a file that could not be read could not be checked either
+    pub fn has_failures(&self) -> bool {
+        !self.errors.is_empty() || self.issues.iter().any(|issue| issue.status.is_failure())
+    }
+}
+
+// rehash every file of an initialized directory, ignoring the hashes cached in tracker.json, and compare it with that saved state
+pub fn check_integrity(dir: &Path) -> Result<IntegrityReport, String> {
+    let mut current = DirTracker::new(dir)?;
+    current.dir_initialized()?;
+    let saved = current.load_tracker().map_err(|e| format!("Could not load tracker: {}", e))?;
+    // skip what the syncs skipped, so excluded files are not reported as new
+    for pattern in saved.get_ignore() {
+        current.add_ignore(pattern);
+    }
+    current.set_links(saved.get_links());
+    current.set_checksum(true);
+    current.import_files_from_directory(true);
+
+    let mut issues = Vec::new();
+    for (path, file) in current.get_file_hashmap() {
+        let status = match saved.get_file(path) {
+            None => IntegrityStatus::New,
+            Some(saved_file) if saved_file.get_current_file_hash() == file.get_current_file_hash() => continue,
+            Some(saved_file) if saved_file.has_same_mtime(file) => IntegrityStatus::Bitrot,
+            Some(_) => IntegrityStatus::Modified,
+        };
+        issues.push(IntegrityIssue {
+            path: path.clone(),
+            status,
+            expected_hash: saved.get_file(path).map(|f| f.get_current_file_hash().to_string()),
+            actual_hash: Some(file.get_current_file_hash().to_string()),
+        });
+    }
+    for (path, file) in saved.get_file_hashmap() {
+        if !current.has_file(path) && !current.is_ignored(path) && !current.is_unreadable(path) {
+            issues.push(IntegrityIssue {
+                path: path.clone(),
+                status: IntegrityStatus::Missing,
+                expected_hash: Some(file.get_current_file_hash().to_string()),
+                actual_hash: None,
+            });
+        }
+    }
+    issues.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(IntegrityReport {
+        checked: current.get_file_hashmap().values().filter(|file| !file.is_dir()).count(),
+        issues,
+        // links the policy skipped were never synced, so they are not something that could not be checked
+        errors: current.get_errors().iter().filter(|e| !e.is_skipped_link()).cloned().collect(),
+    })
+}
@@ -1,2 +1,16 @@
 pub mod dir_tracker;   
-pub mod file_tracker;
\ No newline at end of file
+pub mod file_tracker;
+pub mod tracker_config;
+pub mod sync_change;
+pub mod sync_plan;
+pub mod sync_journal;
+pub mod conflict;
+pub mod copy_engine;
+pub mod sync_options;
+pub mod sync_observer;
+pub mod syncer;
+pub mod version_store;
+pub mod ignore_matcher;
+pub mod drive_finder;
+pub mod dir_watcher;
+pub mod integrity;
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// how a single file changed on one side compared to the last synced state (tracker.json)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileChange {
+    Unchanged,
+    Added,
+    Modified,
+    Deleted,
+}
+
+// which way a change has to travel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncDirection {
+    SrcToDest,
+    DestToSrc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    Copy(SyncDirection),
+    Delete(SyncDirection),
+    // changed on both sides since the last sync
+    Conflict,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncChange {
+    relative_path: String,
+    src_change: FileChange,
+    dest_change: FileChange,
+    action: SyncAction,
+}
+
+impl SyncChange {
+    pub fn new(relative_path: &str, src_change: FileChange, dest_change: FileChange, action: SyncAction) -> Self {
+        SyncChange {
+            relative_path: relative_path.to_string(),
+            src_change,
+            dest_change,
+            action,
+        }
+    }
+
+    pub fn get_relative_path(&self) -> &str {
+        &self.relative_path
+    }
+
+    pub fn get_src_change(&self) -> FileChange {
+        self.src_change
+    }
+
+    pub fn get_dest_change(&self) -> FileChange {
+        self.dest_change
+    }
+
+    pub fn get_action(&self) -> SyncAction {
+        self.action
+    }
+}
+
+impl fmt::Display for FileChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FileChange::Unchanged => "Unchanged",
+            FileChange::Added => "New",
+            FileChange::Modified => "Modified",
+            FileChange::Deleted => "Deleted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for SyncDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncDirection::SrcToDest => write!(f, "src -> dest"),
+            SyncDirection::DestToSrc => write!(f, "dest -> src"),
+        }
+    }
+}
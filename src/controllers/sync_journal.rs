@@ -0,0 +1,104 @@
+use std::{collections::HashSet, fs::{self, File, OpenOptions}, io::Write, path::{Path, PathBuf}, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::sync_plan::SyncPlan;
+use crate::helpers::error::SyncError;
+use crate::helpers::file_ops::write_file_atomic;
+
+// one line of the journal, the plan comes first and every finished operation is appended after it
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JournalRecord {
+    Plan(SyncPlan),
+    Done { path: String },
+}
+
+// the operations of a running sync and the ones already finished, kept in .hard_sync_cli/journal of the destination
+// so a sync that died partway can be resumed without planning it again
+pub struct SyncJournal {
+    path: PathBuf,
+    file: Mutex<File>,
+    plan: SyncPlan,
+    completed: HashSet<String>,
+}
+
+impl SyncJournal {
+    pub fn get_journal_path(dest: &Path) -> PathBuf {
+        dest.join(".hard_sync_cli").join("journal")
+    }
+
+    pub fn exists(dest: &Path) -> bool {
+        Self::get_journal_path(dest).exists()
+    }
+
+    // write the plan of a new sync, replacing the journal of any earlier one
+    pub fn start(dest: &Path, plan: &SyncPlan) -> Result<SyncJournal, SyncError> {
+        let path = Self::get_journal_path(dest);
+        let mut content = serde_json::to_vec(&JournalRecord::Plan(plan.clone())).map_err(|e| SyncError::invalid_state(&path, e))?;
+        content.push(b'\n');
+        write_file_atomic(&path, &content).map_err(|e| SyncError::io(&path, e))?;
+        Self::open(path, plan.clone(), HashSet::new())
+    }
+
+    // read the journal left by an interrupted sync
+    pub fn resume(dest: &Path) -> Result<SyncJournal, SyncError> {
+        let path = Self::get_journal_path(dest);
+        if !path.exists() {
+            return Err(SyncError::invalid_state(dest, "there is no interrupted sync to resume"));
+        }
+        let content = fs::read_to_string(&path).map_err(|e| SyncError::io(&path, e))?;
+        let mut lines = content.lines();
+        let plan = match lines.next().map(serde_json::from_str::<JournalRecord>) {
+            Some(Ok(JournalRecord::Plan(plan))) => plan,
+            _ => return Err(SyncError::invalid_state(&path, "the journal does not start with a plan")),
+        };
+        // the last line may have been cut off by the crash, the operation it records is simply checked again
+        let completed = lines
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(JournalRecord::Done { path }) => Some(path),
+                _ => None,
+            })
+            .collect();
+        Self::open(path, plan, completed)
+    }
+
+    fn open(path: PathBuf, plan: SyncPlan, completed: HashSet<String>) -> Result<SyncJournal, SyncError> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| SyncError::io(&path, e))?;
+        Ok(SyncJournal {
+            path,
+            file: Mutex::new(file),
+            plan,
+            completed,
+        })
+    }
+
+    pub fn get_plan(&self) -> &SyncPlan {
+        &self.plan
+    }
+
+    pub fn get_completed(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_completed(&self, path: &str) -> bool {
+        self.completed.contains(path)
+    }
+
+    // mark the operation on `path` as finished, called from the copy workers as each file lands
+    pub fn record(&self, path: &str) -> Result<(), SyncError> {
+        let mut line = serde_json::to_vec(&JournalRecord::Done { path: path.to_string() }).map_err(|e| SyncError::invalid_state(&self.path, e))?;
+        line.push(b'\n');
+        self.file.lock().unwrap()
+            .write_all(&line)
+            .map_err(|e| SyncError::io(&self.path, e))
+    }
+
+    // the sync finished, nothing is left to resume
+    pub fn finish(self) -> Result<(), SyncError> {
+        fs::remove_file(&self.path).map_err(|e| SyncError::io(&self.path, e))
+    }
+}
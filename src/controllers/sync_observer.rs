@@ -0,0 +1,49 @@
+use super::conflict::{ConflictPolicy, ConflictRecord};
+use super::sync_plan::SyncPlan;
+use crate::helpers::output::{PlanEntry, SyncSummary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Info,
+    Success,
+    Warning,
+}
+
+// receives what a running sync does, as it does it
+// every method does nothing by default, so an observer only implements the events it shows
+pub trait SyncObserver {
+    // the plan about to run, before any of its entries is reported
+    fn on_plan(&mut self, _plan: &SyncPlan) {}
+
+    // a file of the plan, reported as its operation runs (or would run, in a dry run)
+    fn on_entry(&mut self, _entry: &PlanEntry) {}
+
+    // something that failed, `path` is set when only that file was skipped and the sync went on
+    fn on_error(&mut self, _path: Option<&str>, _message: &str) {}
+
+    // a status line, like a directory being initialized or temp files being cleaned up
+    fn on_message(&mut self, _kind: MessageKind, _message: &str) {}
+
+    // a file that changed on both sides and what the policy did with it
+    fn on_conflict(&mut self, _policy: ConflictPolicy, _record: &ConflictRecord) {}
+
+    fn on_copy_start(&mut self, _total_files: usize, _total_bytes: u64) {}
+
+    // called about ten times a second while files are being copied
+    fn on_copy_progress(&mut self, _files_done: usize, _bytes_done: u64) {}
+
+    fn on_file_copied(&mut self, _path: &str, _files_done: usize, _bytes_done: u64) {}
+
+    fn on_copy_finish(&mut self, _files_done: usize, _bytes_done: u64) {}
+
+    // a watch finished syncing a batch of changes
+    fn on_watch_sync(&mut self, _summary: &SyncSummary) {}
+
+    // checked about once a second while watching, the watch returns once this is false
+    fn keep_watching(&mut self) -> bool {
+        true
+    }
+}
+
+// for callers that only want the summary
+impl SyncObserver for () {}
@@ -0,0 +1,173 @@
+use std::path::{Path, PathBuf};
+
+use super::conflict::ConflictPolicy;
+use super::copy_engine::DEFAULT_COPY_WORKERS;
+use super::tracker_config::{LinkPolicy, PreserveMetadata, SyncMode, VerifyLevel};
+use super::version_store::RetentionPolicy;
+
+// everything that decides how a `Syncer` syncs two directories
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    src: PathBuf,
+    dest: PathBuf,
+    mode: SyncMode,
+    // set up the tracker of a directory that was never synced instead of failing
+    init: bool,
+    dry_run: bool,
+    // rehash every file instead of reusing the hashes saved in tracker.json
+    checksum: bool,
+    exclude: Vec<String>,
+    // None only looks for conflicts in two way mode, where they are skipped
+    conflict: Option<ConflictPolicy>,
+    // move overwritten and deleted files into .hard_sync_cli/versions
+    backup: bool,
+    retention: Option<RetentionPolicy>,
+    verify: VerifyLevel,
+    // attributes copied along with the content, and synced on their own when only they changed
+    preserve: PreserveMetadata,
+    links: LinkPolicy,
+    copy_workers: usize,
+}
+
+impl SyncOptions {
+    pub fn new(src: &Path, dest: &Path) -> Self {
+        SyncOptions {
+            src: src.to_path_buf(),
+            dest: dest.to_path_buf(),
+            mode: SyncMode::OneWay,
+            init: false,
+            dry_run: false,
+            checksum: false,
+            exclude: Vec::new(),
+            conflict: None,
+            backup: false,
+            retention: None,
+            verify: VerifyLevel::None,
+            preserve: PreserveMetadata::default(),
+            links: LinkPolicy::default(),
+            copy_workers: DEFAULT_COPY_WORKERS,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: SyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_init(mut self, init: bool) -> Self {
+        self.init = init;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn with_exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude.extend(patterns);
+        self
+    }
+
+    pub fn with_conflict(mut self, policy: Option<ConflictPolicy>) -> Self {
+        self.conflict = policy;
+        self
+    }
+
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    // old backup runs are pruned after each sync, only when backups are on
+    pub fn with_retention(mut self, retention: Option<RetentionPolicy>) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_verify(mut self, verify: VerifyLevel) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn with_preserve(mut self, preserve: PreserveMetadata) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    pub fn with_links(mut self, links: LinkPolicy) -> Self {
+        self.links = links;
+        self
+    }
+
+    pub fn with_copy_workers(mut self, workers: usize) -> Self {
+        self.copy_workers = workers.max(1);
+        self
+    }
+
+    pub fn get_src(&self) -> &Path {
+        &self.src
+    }
+
+    pub fn get_dest(&self) -> &Path {
+        &self.dest
+    }
+
+    pub fn get_mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn get_init(&self) -> bool {
+        self.init
+    }
+
+    pub fn get_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn get_checksum(&self) -> bool {
+        self.checksum
+    }
+
+    pub fn get_exclude(&self) -> &[String] {
+        &self.exclude
+    }
+
+    pub fn get_conflict(&self) -> Option<ConflictPolicy> {
+        self.conflict
+    }
+
+    pub fn get_backup(&self) -> bool {
+        self.backup
+    }
+
+    pub fn get_retention(&self) -> Option<RetentionPolicy> {
+        self.retention
+    }
+
+    pub fn get_verify(&self) -> VerifyLevel {
+        self.verify
+    }
+
+    pub fn get_preserve(&self) -> PreserveMetadata {
+        self.preserve
+    }
+
+    pub fn get_links(&self) -> LinkPolicy {
+        self.links
+    }
+
+    pub fn get_copy_workers(&self) -> usize {
+        self.copy_workers
+    }
+
+    // files deleted on one side are deleted on the other
+    pub fn is_deleting(&self) -> bool {
+        self.mode != SyncMode::OneWay
+    }
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use super::dir_tracker::{DirTracker, IgnoredEntry};
+use super::file_tracker::{FileTracker, DIR_HASH};
+use super::sync_change::{FileChange, SyncAction, SyncChange, SyncDirection};
+use super::tracker_config::{LinkPolicy, PreserveMetadata};
+use crate::helpers::file_ops::{is_same_file, write_file_atomic};
+
+const PLAN_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OperationKind {
+    Copy,
+    Delete,
+    // same content on both sides, only the times, permissions or owner are copied over
+    Metadata,
+    // changed on both sides, left to the conflict policy
+    Conflict,
+}
+
+// one step of a plan, with the hash each side had when it was planned (None when the file did not exist)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncOperation {
+    kind: OperationKind,
+    path: String,
+    direction: SyncDirection,
+    size: u64,
+    src_hash: Option<String>,
+    dest_hash: Option<String>,
+    src_change: FileChange,
+    dest_change: FileChange,
+    // the file being copied is a link that is recreated as a link
+    #[serde(default)]
+    link: bool,
+    // the path is a directory, created, removed or given its attributes instead of copied
+    #[serde(default)]
+    dir: bool,
+    // the file being copied is a hard link to this path, which it is linked to instead of copying the data again
+    #[serde(default)]
+    hard_link: Option<String>,
+}
+
+impl SyncOperation {
+    pub fn new(change: &SyncChange, src_file: Option<&FileTracker>, dest_file: Option<&FileTracker>) -> Self {
+        let (kind, direction) = match change.get_action() {
+            SyncAction::Copy(direction) => (OperationKind::Copy, direction),
+            SyncAction::Delete(direction) => (OperationKind::Delete, direction),
+            SyncAction::Conflict => (OperationKind::Conflict, SyncDirection::SrcToDest),
+        };
+        // the file being copied, or the one being deleted
+        let file = match (kind, direction) {
+            (OperationKind::Copy, SyncDirection::SrcToDest) | (OperationKind::Delete, SyncDirection::DestToSrc) => src_file,
+            (OperationKind::Copy, SyncDirection::DestToSrc) | (OperationKind::Delete, SyncDirection::SrcToDest) => dest_file,
+            (OperationKind::Metadata | OperationKind::Conflict, _) => src_file.or(dest_file),
+        };
+        SyncOperation {
+            kind,
+            path: change.get_relative_path().to_string(),
+            direction,
+            size: file.map(|f| f.get_size()).unwrap_or(0),
+            src_hash: src_file.map(|f| f.get_current_file_hash().to_string()),
+            dest_hash: dest_file.map(|f| f.get_current_file_hash().to_string()),
+            src_change: change.get_src_change(),
+            dest_change: change.get_dest_change(),
+            link: file.is_some_and(|f| f.is_link()),
+            dir: file.is_some_and(|f| f.is_dir()),
+            hard_link: file.filter(|_| kind == OperationKind::Copy).and_then(|f| f.get_hard_link()).map(str::to_string),
+        }
+    }
+
+    pub fn get_kind(&self) -> OperationKind {
+        self.kind
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_direction(&self) -> SyncDirection {
+        self.direction
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_src_hash(&self) -> Option<&str> {
+        self.src_hash.as_deref()
+    }
+
+    pub fn get_dest_hash(&self) -> Option<&str> {
+        self.dest_hash.as_deref()
+    }
+
+    // the hash of the file being copied
+    pub fn get_source_hash(&self) -> Option<&str> {
+        match self.direction {
+            SyncDirection::SrcToDest => self.get_src_hash(),
+            SyncDirection::DestToSrc => self.get_dest_hash(),
+        }
+    }
+
+    // the hash the operation expects at the path it writes to or deletes
+    pub fn get_target_hash(&self) -> Option<&str> {
+        match self.direction {
+            SyncDirection::SrcToDest => self.get_dest_hash(),
+            SyncDirection::DestToSrc => self.get_src_hash(),
+        }
+    }
+
+    pub fn is_link(&self) -> bool {
+        self.link
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    pub fn get_hard_link(&self) -> Option<&str> {
+        self.hard_link.as_deref()
+    }
+
+    pub fn get_src_change(&self) -> FileChange {
+        self.src_change
+    }
+
+    pub fn get_dest_change(&self) -> FileChange {
+        self.dest_change
+    }
+
+    pub fn to_change(&self) -> SyncChange {
+        let action = match self.kind {
+            OperationKind::Copy | OperationKind::Metadata => SyncAction::Copy(self.direction),
+            OperationKind::Delete => SyncAction::Delete(self.direction),
+            OperationKind::Conflict => SyncAction::Conflict,
+        };
+        SyncChange::new(&self.path, self.src_change, self.dest_change, action)
+    }
+
+    // refuse the operation when either side no longer has the content it had when the plan was made
+    // `links` is the link policy the plan was made with, so links are compared the way they were scanned
+    pub fn check(&self, src: &Path, dest: &Path, links: LinkPolicy) -> Result<(), String> {
+        let (from, to, from_hash, to_hash) = match self.direction {
+            SyncDirection::SrcToDest => (src, dest, &self.src_hash, &self.dest_hash),
+            SyncDirection::DestToSrc => (dest, src, &self.dest_hash, &self.src_hash),
+        };
+        if current_hash(&from.join(&self.path), links)? != *from_hash {
+            return Err("the source changed since the plan was made".to_string());
+        }
+        if current_hash(&to.join(&self.path), links)? != *to_hash {
+            return Err("the destination changed since the plan was made".to_string());
+        }
+        Ok(())
+    }
+
+    // whether the disk already looks the way the operation would leave it, like after a sync that died before recording it
+    pub fn is_applied(&self, src: &Path, dest: &Path, links: LinkPolicy) -> bool {
+        let to = match self.direction {
+            SyncDirection::SrcToDest => dest,
+            SyncDirection::DestToSrc => src,
+        };
+        let target = to.join(&self.path);
+        match self.kind {
+            OperationKind::Copy => {
+                current_hash(&target, links).ok().flatten().as_deref() == self.get_source_hash()
+                    && self.hard_link.as_ref().is_none_or(|hard_link| is_same_file(&to.join(hard_link), &target))
+            }
+            OperationKind::Delete => fs::symlink_metadata(&target).is_err(),
+            // setting the same attributes again does no harm
+            OperationKind::Metadata | OperationKind::Conflict => false,
+        }
+    }
+}
+
+// every operation needed to bring the destination (and in two way mode the source) up to date
+// saved by `hsync plan` and run later by `hsync apply`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPlan {
+    version: u32,
+    created: String,
+    src: String,
+    dest: String,
+    two_way: bool,
+    // the policy conflicts are resolved with when applied, unless another one is passed
+    conflict_policy: Option<String>,
+    // ignore patterns the trees were scanned with, so apply records the same files in tracker.json
+    ignore: Vec<String>,
+    // the attributes copied along with the content, plans written before they were kept preserve none
+    #[serde(default)]
+    preserve: PreserveMetadata,
+    // what the scans did with links, plans written before links were synced skipped them
+    #[serde(default)]
+    links: LinkPolicy,
+    operations: Vec<SyncOperation>,
+    // what the ignore patterns kept out of the sync on either side, reported but never touched
+    #[serde(default)]
+    ignored: Vec<IgnoredEntry>,
+}
+
+impl SyncPlan {
+    fn new(src_dir: &DirTracker, dest_dir: &DirTracker, two_way: bool) -> Self {
+        SyncPlan {
+            version: PLAN_VERSION,
+            created: Local::now().to_rfc3339(),
+            src: absolute_path(src_dir.get_path()),
+            dest: absolute_path(dest_dir.get_path()),
+            two_way,
+            conflict_policy: None,
+            ignore: dest_dir.get_ignore(),
+            preserve: PreserveMetadata::default(),
+            links: dest_dir.get_links(),
+            operations: Vec::new(),
+            ignored: ignored_entries(src_dir, dest_dir),
+        }
+    }
+
+    // copy what is new or changed in the source, and with `delete` remove what the source no longer has
+    pub fn one_way(src_dir: &DirTracker, dest_dir: &DirTracker, delete: bool) -> Self {
+        let mut plan = SyncPlan::new(src_dir, dest_dir, false);
+        for file in src_dir.get_dir_diff(dest_dir) {
+            let path = file.get_relative_path(Path::new(src_dir.get_path()));
+            let dest_file = dest_dir.get_file(path);
+            let src_change = match dest_file {
+                Some(_) => FileChange::Modified,
+                None => FileChange::Added,
+            };
+            let change = SyncChange::new(path, src_change, FileChange::Unchanged, SyncAction::Copy(SyncDirection::SrcToDest));
+            plan.operations.push(SyncOperation::new(&change, Some(&file), dest_file));
+        }
+        if delete {
+            for file in src_dir.get_dir_deletions(dest_dir) {
+                let path = file.get_relative_path(Path::new(dest_dir.get_path()));
+                let change = SyncChange::new(path, FileChange::Deleted, FileChange::Unchanged, SyncAction::Delete(SyncDirection::SrcToDest));
+                plan.operations.push(SyncOperation::new(&change, None, Some(&file)));
+            }
+        }
+        plan.skip_unreadable(src_dir, dest_dir);
+        plan
+    }
+
+    // changes from both sides, each compared against the baseline loaded from its own tracker.json
+    pub fn two_way(src_dir: &DirTracker, src_baseline: &DirTracker, dest_dir: &DirTracker, dest_baseline: &DirTracker) -> Self {
+        let mut plan = SyncPlan::new(src_dir, dest_dir, true);
+        for change in src_dir.get_two_way_diff(src_baseline, dest_dir, dest_baseline) {
+            let path = change.get_relative_path();
+            plan.operations.push(SyncOperation::new(&change, src_dir.get_file(path), dest_dir.get_file(path)));
+        }
+        plan.skip_unreadable(src_dir, dest_dir);
+        plan
+    }
+
+    // a file the scan could not read looks deleted or new, so nothing is done with it until it can be read again
+    fn skip_unreadable(&mut self, src_dir: &DirTracker, dest_dir: &DirTracker) {
+        self.operations.retain(|op| !src_dir.is_unreadable(&op.path) && !dest_dir.is_unreadable(&op.path));
+    }
+
+    // turn copies of files that also changed in the destination since the last sync into conflicts
+    pub fn detect_conflicts(&mut self, src_dir: &DirTracker, src_baseline: &DirTracker, dest_dir: &DirTracker, dest_baseline: &DirTracker) {
+        for operation in self.operations.iter_mut().filter(|op| op.kind == OperationKind::Copy) {
+            let dest_change = dest_dir.get_file_change(dest_baseline, &operation.path);
+            let src_change = src_dir.get_file_change(src_baseline, &operation.path);
+            if src_change != FileChange::Unchanged && dest_change != FileChange::Unchanged {
+                operation.kind = OperationKind::Conflict;
+                operation.src_change = src_change;
+                operation.dest_change = dest_change;
+            }
+        }
+    }
+
+    // keep the attributes of copied files, and copy the attributes of files whose content is already the same in one way mode
+    pub fn set_preserve(&mut self, src_dir: &DirTracker, dest_dir: &DirTracker, preserve: PreserveMetadata) {
+        self.preserve = preserve;
+        if self.two_way || !preserve.is_any() {
+            return;
+        }
+        let src_root = Path::new(src_dir.get_path());
+        let mut files: Vec<&FileTracker> = src_dir.get_file_hashmap().values().collect();
+        files.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+        for file in files {
+            let path = file.get_relative_path(src_root);
+            let Some(dest_file) = dest_dir.get_file(path) else {
+                continue;
+            };
+            // a link whose target is unchanged is left alone, setting its attributes would change the file it points to
+            if file.is_link() || file.get_current_file_hash() != dest_file.get_current_file_hash() || file.has_same_metadata(dest_file, preserve) || dest_dir.is_ignored(path) {
+                continue;
+            }
+            let change = SyncChange::new(path, FileChange::Modified, FileChange::Unchanged, SyncAction::Copy(SyncDirection::SrcToDest));
+            let mut operation = SyncOperation::new(&change, Some(file), Some(dest_file));
+            operation.kind = OperationKind::Metadata;
+            self.operations.push(operation);
+        }
+        self.skip_unreadable(src_dir, dest_dir);
+    }
+
+    pub fn get_preserve(&self) -> PreserveMetadata {
+        self.preserve
+    }
+
+    pub fn get_links(&self) -> LinkPolicy {
+        self.links
+    }
+
+    pub fn set_conflict_policy(&mut self, policy: Option<String>) {
+        self.conflict_policy = policy;
+    }
+
+    pub fn get_created(&self) -> &str {
+        &self.created
+    }
+
+    pub fn get_src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn get_dest(&self) -> &str {
+        &self.dest
+    }
+
+    pub fn is_two_way(&self) -> bool {
+        self.two_way
+    }
+
+    pub fn get_conflict_policy(&self) -> Option<&String> {
+        self.conflict_policy.as_ref()
+    }
+
+    pub fn get_ignore(&self) -> &[String] {
+        &self.ignore
+    }
+
+    pub fn get_ignored(&self) -> &[IgnoredEntry] {
+        &self.ignored
+    }
+
+    pub fn get_operations(&self) -> &[SyncOperation] {
+        &self.operations
+    }
+
+    // drop the operations `keep` returns false for
+    pub fn retain_operations(&mut self, keep: impl FnMut(&SyncOperation) -> bool) {
+        self.operations.retain(keep);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_file_atomic(path, &content).map_err(|e| format!("Failed to write plan {:?}: {}", path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<SyncPlan, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Could not read plan {:?}: {}", path, e))?;
+        let plan: SyncPlan = serde_json::from_str(&content).map_err(|e| format!("Invalid plan {:?}: {}", path, e))?;
+        if plan.version != PLAN_VERSION {
+            return Err(format!("Plan {:?} has version {}, expected {}", path, plan.version, PLAN_VERSION));
+        }
+        Ok(plan)
+    }
+}
+
+// the entries ignored in the source, then those only the destination has
+fn ignored_entries(src_dir: &DirTracker, dest_dir: &DirTracker) -> Vec<IgnoredEntry> {
+    let in_src: HashSet<&str> = src_dir.get_ignored().iter().map(|e| e.get_path()).collect();
+    let mut ignored = src_dir.get_ignored().to_vec();
+    ignored.extend(dest_dir.get_ignored().iter().filter(|e| !in_src.contains(e.get_path())).cloned());
+    ignored.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+    ignored
+}
+
+// hash of the file at `path`, or of the link itself when links are copied, None when there is nothing there
+// a directory has the hash every directory is tracked with
+fn current_hash(path: &Path, links: LinkPolicy) -> Result<Option<String>, String> {
+    if links == LinkPolicy::Copy {
+        if let Ok(target) = fs::read_link(path) {
+            return Ok(Some(FileTracker::hash_link(&target)));
+        }
+    }
+    if !path.exists() {
+        return Ok(None);
+    }
+    if path.is_dir() {
+        return Ok(Some(DIR_HASH.to_string()));
+    }
+    FileTracker::hash_file(path)
+        .map(Some)
+        .map_err(|e| format!("could not read {:?}: {}", path, e))
+}
+
+// plans may be applied from another working directory
+fn absolute_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
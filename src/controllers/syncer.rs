@@ -0,0 +1,914 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, sync::Mutex, time::{Duration, Instant}};
+
+use super::conflict::{ConflictPolicy, ConflictResolver};
+use super::copy_engine::{CopyEngine, CopyJob, CopyReport};
+use super::dir_tracker::DirTracker;
+use super::dir_watcher::DirWatcher;
+use super::file_tracker::FileTracker;
+use super::sync_change::SyncDirection;
+use super::sync_journal::SyncJournal;
+use super::sync_observer::{MessageKind, SyncObserver};
+use super::sync_options::SyncOptions;
+use super::sync_plan::{OperationKind, SyncPlan};
+use super::tracker_config::{PreserveMetadata, SyncMode};
+use super::version_store::{RetentionPolicy, VersionStore};
+use crate::helpers::error::SyncError;
+use crate::helpers::file_ops::{copy_attributes, copy_dir_attributes, hard_link_atomic, temp_path};
+use crate::helpers::output::{EntryStatus, PlanEntry, SyncSummary};
+
+// how often a watch asks its observer whether to go on
+const WATCH_POLL: Duration = Duration::from_secs(1);
+
+// the observer of a single run and the totals added up for it
+struct Run<'a> {
+    observer: &'a mut dyn SyncObserver,
+    summary: SyncSummary,
+    started: Instant,
+    // operations not run because their files changed since they were planned
+    refused: usize,
+}
+
+impl<'a> Run<'a> {
+    fn new(observer: &'a mut dyn SyncObserver, dry_run: bool) -> Self {
+        Run {
+            observer,
+            summary: SyncSummary::new(dry_run),
+            started: Instant::now(),
+            refused: 0,
+        }
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.summary.is_dry_run()
+    }
+
+    fn entry(&mut self, entry: PlanEntry) {
+        self.summary.add_entry(entry.get_status());
+        self.observer.on_entry(&entry);
+    }
+
+    fn error(&mut self, path: Option<&str>, message: &str) {
+        self.summary.add_error();
+        self.observer.on_error(path, message);
+    }
+
+    fn refuse(&mut self, path: &str, message: &str) {
+        self.refused += 1;
+        self.error(Some(path), message);
+    }
+
+    fn message(&mut self, kind: MessageKind, message: &str) {
+        self.observer.on_message(kind, message);
+    }
+
+    fn get_error_count(&self) -> usize {
+        self.summary.get_errors()
+    }
+
+    fn finish(mut self) -> SyncSummary {
+        self.summary.set_duration(self.started.elapsed());
+        self.summary
+    }
+}
+
+// directory operations held back until the files of a sync are copied and deleted
+#[derive(Default)]
+struct DirWork {
+    // directories to remove once emptied, full and relative path
+    removals: Vec<(PathBuf, String)>,
+    attributes: Vec<DirAttributes>,
+}
+
+// a directory given the attributes of the one it was synced from
+struct DirAttributes {
+    from: PathBuf,
+    to: PathBuf,
+    path: String,
+    // the attributes are all that changed, otherwise the directory was just created
+    metadata_only: bool,
+}
+
+// syncs the two directories of its options, telling the observer passed to each call what it does
+// errors that stop a run are returned, failures on single files are reported to the observer and the run goes on
+pub struct Syncer {
+    options: SyncOptions,
+}
+
+impl Syncer {
+    pub fn new(options: SyncOptions) -> Self {
+        Syncer { options }
+    }
+
+    pub fn get_options(&self) -> &SyncOptions {
+        &self.options
+    }
+
+    // scan both directories and work out the operations of the sync, without running any of them
+    pub fn plan(&self, observer: &mut dyn SyncObserver) -> Result<SyncPlan, SyncError> {
+        let mut run = Run::new(observer, true);
+        self.check_dirs(self.options.get_src(), self.options.get_dest())?;
+        let (_, _, plan) = self.scan_and_plan(&mut run)?;
+        run.observer.on_plan(&plan);
+        Ok(plan)
+    }
+
+    // report the operations of a plan the way a dry run does, conflicts are resolved when it is applied
+    pub fn preview(&self, plan: &SyncPlan, observer: &mut dyn SyncObserver) -> SyncSummary {
+        let mut run = Run::new(observer, true);
+        self.execute(plan, None, false, None, &mut run);
+        run.finish()
+    }
+
+    // scan, plan and run the sync
+    pub fn run(&self, observer: &mut dyn SyncObserver) -> Result<SyncSummary, SyncError> {
+        let (src, dest) = (self.options.get_src(), self.options.get_dest());
+        let dry_run = self.options.get_dry_run();
+        let mut run = Run::new(observer, dry_run);
+        self.check_dirs(src, dest)?;
+        let (mut src_dir, mut dest_dir, plan) = self.scan_and_plan(&mut run)?;
+        run.observer.on_plan(&plan);
+        if dry_run {
+            run.message(MessageKind::Info, "Dry run turned on");
+        }
+
+        // in two way mode conflicts are always looked for, and skipped unless a policy is passed
+        let two_way = self.options.get_mode() == SyncMode::TwoWay;
+        // only the directories this sync writes into hold its temp files, a one way source is never touched
+        if !dry_run {
+            remove_temp_files(&mut run, dest, dest_dir.get_temp_files());
+            if two_way {
+                remove_temp_files(&mut run, src, src_dir.get_temp_files());
+            }
+        }
+        let conflict_policy = match two_way {
+            true => Some(self.options.get_conflict().unwrap_or(ConflictPolicy::Skip)),
+            false => self.options.get_conflict(),
+        };
+        let mut resolver = self.resolver(conflict_policy, src, dest, &plan, dry_run);
+        // the journal lets an interrupted run be finished with `resume`
+        let journal = match dry_run || plan.is_empty() {
+            true => None,
+            false => {
+                if SyncJournal::exists(dest) {
+                    run.message(MessageKind::Warning, "Replacing the journal of an interrupted sync, pass --resume to continue that one instead");
+                }
+                Some(SyncJournal::start(dest, &plan)?)
+            }
+        };
+        let scan_errors = run.get_error_count();
+        self.execute(&plan, resolver.as_mut(), false, journal.as_ref(), &mut run);
+        if self.options.get_backup() && !dry_run {
+            prune_versions(&mut run, &VersionStore::new(dest), self.options.get_retention());
+            if two_way {
+                prune_versions(&mut run, &VersionStore::new(src), self.options.get_retention());
+            }
+        }
+
+        // record what the destination (and the source, if it is initialized) looks like after this sync
+        if !dry_run {
+            save_sync_states(&mut src_dir, &mut dest_dir, &mut run);
+        }
+        finish_journal(journal, &mut run, scan_errors);
+        Ok(run.finish())
+    }
+
+    // run a plan made earlier, refusing every operation whose files changed since it was made
+    // the plan is run between its own directories, the ones in the options are not used
+    pub fn apply(&self, plan: &SyncPlan, observer: &mut dyn SyncObserver) -> Result<SyncSummary, SyncError> {
+        let (src, dest) = (Path::new(plan.get_src()), Path::new(plan.get_dest()));
+        let dry_run = self.options.get_dry_run();
+        let mut run = Run::new(observer, dry_run);
+        self.check_dirs(src, dest)?;
+        run.message(MessageKind::Info, format!("Applying plan made {} ({} -> {})", plan.get_created(), plan.get_src(), plan.get_dest()).as_str());
+        run.observer.on_plan(plan);
+        if !dry_run {
+            remove_planned_temp_files(&mut run, plan);
+        }
+        // the policy in the options wins over the one saved in the plan
+        let conflict_policy = match (self.options.get_conflict(), plan.get_conflict_policy()) {
+            (Some(policy), _) => Some(policy),
+            (None, Some(v)) => Some(ConflictPolicy::from_name(v).map_err(SyncError::invalid_options)?),
+            (None, None) => None,
+        };
+        if dry_run {
+            run.message(MessageKind::Info, "Dry run turned on");
+        }
+        let mut resolver = self.resolver(conflict_policy, src, dest, plan, dry_run);
+        self.execute(plan, resolver.as_mut(), true, None, &mut run);
+        // saving now would make the changes the plan did not know about look synced, the next sync has to see them
+        if !dry_run && run.refused > 0 {
+            run.message(MessageKind::Warning, format!("{} operations were refused, the sync state is left as it was", run.refused).as_str());
+        } else if !dry_run {
+            let (mut src_dir, mut dest_dir) = scan_planned_dirs(plan)?;
+            save_sync_states(&mut src_dir, &mut dest_dir, &mut run);
+            report_scan_errors(&mut run, &[&src_dir, &dest_dir]);
+        }
+        Ok(run.finish())
+    }
+
+    // continue the sync journaled in the destination, skipping what it finished and files that already reached their target
+    pub fn resume(&self, observer: &mut dyn SyncObserver) -> Result<SyncSummary, SyncError> {
+        let (src, dest) = (self.options.get_src(), self.options.get_dest());
+        if self.options.get_dry_run() {
+            return Err(SyncError::invalid_options("An interrupted sync can not be resumed as a dry run"));
+        }
+        let mut run = Run::new(observer, false);
+        self.check_dirs(src, dest)?;
+
+        let journal = SyncJournal::resume(dest)?;
+        let mut plan = journal.get_plan().clone();
+        let is_same_dir = |dir: &Path, planned: &str| fs::canonicalize(dir).map(|dir| dir == Path::new(planned)).unwrap_or(false);
+        if !is_same_dir(src, plan.get_src()) || !is_same_dir(dest, plan.get_dest()) {
+            return Err(SyncError::invalid_options(format!(
+                "The interrupted sync went from {} to {}, pass the same directories to resume it",
+                plan.get_src(), plan.get_dest()
+            )));
+        }
+        remove_planned_temp_files(&mut run, &plan);
+        let total = plan.get_operations().len();
+        let links = plan.get_links();
+        plan.retain_operations(|op| !journal.is_completed(op.get_path()) && !op.is_applied(src, dest, links));
+        run.message(MessageKind::Info, format!(
+            "Resuming the sync planned {}: {} operations recorded as done, {} of {} left",
+            plan.get_created(), journal.get_completed(), plan.get_operations().len(), total
+        ).as_str());
+        run.observer.on_plan(&plan);
+
+        // the policy passed now wins over the one the sync was started with, two way syncs skip conflicts by default
+        let conflict_policy = match (self.options.get_conflict(), plan.get_conflict_policy()) {
+            (Some(policy), _) => Some(policy),
+            (None, Some(v)) => Some(ConflictPolicy::from_name(v).map_err(SyncError::invalid_options)?),
+            (None, None) if plan.is_two_way() => Some(ConflictPolicy::Skip),
+            (None, None) => None,
+        };
+        let mut resolver = self.resolver(conflict_policy, src, dest, &plan, false);
+        let errors_before = run.get_error_count();
+        self.execute(&plan, resolver.as_mut(), false, Some(&journal), &mut run);
+
+        // the files the sync did not touch keep their saved hashes, so this scan only rehashes what changed
+        let (mut src_dir, mut dest_dir) = scan_planned_dirs(&plan)?;
+        save_sync_states(&mut src_dir, &mut dest_dir, &mut run);
+        finish_journal(Some(journal), &mut run, errors_before);
+        report_scan_errors(&mut run, &[&src_dir, &dest_dir]);
+        if self.options.get_backup() {
+            prune_versions(&mut run, &VersionStore::new(dest), self.options.get_retention());
+        }
+        Ok(run.finish())
+    }
+
+    // sync once, then copy every file that changes in the source until the observer stops the watch
+    // only one way modes can be watched, with mirror mode files deleted from the source are deleted too
+    pub fn watch(&self, debounce: Duration, observer: &mut dyn SyncObserver) -> Result<(), SyncError> {
+        let (src, dest) = (self.options.get_src(), self.options.get_dest());
+        if self.options.get_mode() == SyncMode::TwoWay {
+            return Err(SyncError::invalid_options("Only one way syncs can be watched"));
+        }
+        let mut run = Run::new(observer, false);
+        self.check_dirs(src, dest)?;
+
+        let mut src_dir = DirTracker::new(src)?;
+        let mut dest_dir = DirTracker::new(dest)?;
+        self.init_dir(&mut dest_dir, "Destination", &mut run)?;
+        for pattern in self.options.get_exclude() {
+            dest_dir.add_ignore(pattern.clone());
+        }
+        for pattern in dest_dir.get_ignore() {
+            src_dir.add_ignore(pattern);
+        }
+        src_dir.set_checksum(self.options.get_checksum());
+        dest_dir.set_checksum(self.options.get_checksum());
+        src_dir.set_links(self.options.get_links());
+        dest_dir.set_links(self.options.get_links());
+        src_dir.set_cache_dir(dest);
+
+        // start watching before the first sync so nothing changed during it is missed
+        let watcher = DirWatcher::new(src, debounce)?;
+        rayon::join(
+            || src_dir.import_files_from_directory(true),
+            || dest_dir.import_files_from_directory(true),
+        );
+        report_scan_errors(&mut run, &[&src_dir, &dest_dir]);
+        remove_temp_files(&mut run, dest, dest_dir.get_temp_files());
+        let mut paths: Vec<String> = src_dir.get_file_hashmap().keys().cloned().collect();
+        paths.extend(dest_dir.get_file_hashmap().keys().filter(|k| !src_dir.has_file(k)).cloned());
+        self.sync_watched_paths(&src_dir, &mut dest_dir, paths, &mut run);
+        if self.options.get_backup() {
+            prune_versions(&mut run, &VersionStore::new(dest), self.options.get_retention());
+        }
+        // the source is only read, the hashes of this first scan are what the next watch starts from
+        if let Err(e) = src_dir.save_cache() {
+            run.error(None, format!("Failed to save the source hashes: {}", e).as_str());
+        }
+
+        run.message(MessageKind::Info, format!("Watching {:?} for changes", watcher.get_root()).as_str());
+        while run.observer.keep_watching() {
+            let Some(batch) = watcher.next_batch_timeout(WATCH_POLL)? else {
+                continue;
+            };
+            let changed = src_dir.update_paths(&batch);
+            if !changed.is_empty() {
+                self.sync_watched_paths(&src_dir, &mut dest_dir, changed, &mut run);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_dirs(&self, src: &Path, dest: &Path) -> Result<(), SyncError> {
+        for dir in [src, dest] {
+            if !dir.exists() {
+                return Err(SyncError::NotFound { path: dir.to_string_lossy().to_string() });
+            }
+        }
+        if src == dest {
+            return Err(SyncError::invalid_options("Source and destination directories are same"));
+        }
+        Ok(())
+    }
+
+    // set up the tracker of a directory that was never synced, when the options allow it
+    fn init_dir(&self, dir: &mut DirTracker, name: &str, run: &mut Run) -> Result<(), SyncError> {
+        let Err(e) = dir.dir_initialized() else {
+            return Ok(());
+        };
+        if !self.options.get_init() {
+            return Err(e);
+        }
+        dir.setup_dir_config()?;
+        run.message(MessageKind::Success, format!("{} directory initialized", name).as_str());
+        Ok(())
+    }
+
+    // scan both trees with the ignore rules of the destination, then work out what has to change
+    fn scan_and_plan(&self, run: &mut Run) -> Result<(DirTracker, DirTracker, SyncPlan), SyncError> {
+        let mut src_dir = DirTracker::new(self.options.get_src())?;
+        let mut dest_dir = DirTracker::new(self.options.get_dest())?;
+        self.init_dir(&mut dest_dir, "Destination", run)?;
+        src_dir.set_checksum(self.options.get_checksum());
+        dest_dir.set_checksum(self.options.get_checksum());
+        src_dir.set_links(self.options.get_links());
+        dest_dir.set_links(self.options.get_links());
+        // a source that is not initialized keeps its hashes in the destination
+        src_dir.set_cache_dir(self.options.get_dest());
+
+        // two way sync needs the last synced state of both sides
+        let two_way = self.options.get_mode() == SyncMode::TwoWay;
+        if two_way {
+            self.init_dir(&mut src_dir, "Source", run)?;
+        }
+
+        for pattern in self.options.get_exclude() {
+            dest_dir.add_ignore(pattern.clone());
+        }
+        // the ignore rules live in the destination but reference source paths, so the source walk skips them too
+        for pattern in dest_dir.get_ignore() {
+            src_dir.add_ignore(pattern);
+        }
+        if two_way {
+            for pattern in src_dir.get_ignore() {
+                dest_dir.add_ignore(pattern);
+            }
+        }
+
+        // scan both trees at the same time
+        rayon::join(
+            || src_dir.import_files_from_directory(true),
+            || dest_dir.import_files_from_directory(true),
+        );
+        report_scan_errors(run, &[&src_dir, &dest_dir]);
+
+        let conflict_policy = self.options.get_conflict();
+        let mut plan = match two_way {
+            true => {
+                let src_baseline = src_dir.load_tracker()?;
+                let dest_baseline = dest_dir.load_tracker()?;
+                SyncPlan::two_way(&src_dir, &src_baseline, &dest_dir, &dest_baseline)
+            }
+            false => {
+                let mut plan = SyncPlan::one_way(&src_dir, &dest_dir, self.options.is_deleting());
+                // conflicts are only looked for when a policy is passed, using the state both sides saved on the last sync
+                if conflict_policy.is_some() {
+                    if let (Ok(src_baseline), Ok(dest_baseline)) = (src_dir.load_last_synced(), dest_dir.load_tracker()) {
+                        plan.detect_conflicts(&src_dir, &src_baseline, &dest_dir, &dest_baseline);
+                    }
+                }
+                plan
+            }
+        };
+        plan.set_preserve(&src_dir, &dest_dir, self.options.get_preserve());
+        plan.set_conflict_policy(conflict_policy.map(|policy| policy.to_string()));
+        if plan.is_empty() {
+            run.message(MessageKind::Success, "No diff found");
+        }
+        Ok((src_dir, dest_dir, plan))
+    }
+
+    // conflicts are copied with the link policy and the attributes of the plan, like its other copies
+    fn resolver(&self, policy: Option<ConflictPolicy>, src: &Path, dest: &Path, plan: &SyncPlan, dry_run: bool) -> Option<ConflictResolver> {
+        policy.map(|policy| {
+            let mut resolver = ConflictResolver::new(policy, src, dest, dry_run);
+            resolver.set_versioning(self.options.get_backup() && !dry_run);
+            resolver.set_two_way(plan.is_two_way());
+            resolver.set_links(plan.get_links());
+            resolver.set_preserve(plan.get_preserve());
+            resolver
+        })
+    }
+
+    fn copy_engine(&self, preserve: PreserveMetadata) -> CopyEngine {
+        CopyEngine::new(self.options.get_copy_workers())
+            .with_verify(self.options.get_verify())
+            .with_preserve(preserve)
+    }
+
+    // run every operation of the plan, with `check` each one is first compared against the disk
+    // conflicts go to the resolver, and are only listed when there is none. finished operations are recorded in `journal`
+    fn execute(&self, plan: &SyncPlan, mut resolver: Option<&mut ConflictResolver>, check: bool, journal: Option<&SyncJournal>, run: &mut Run) {
+        let (src, dest) = (Path::new(plan.get_src()), Path::new(plan.get_dest()));
+        let dry_run = run.is_dry_run();
+        let backup = self.options.get_backup() && !dry_run;
+        // keep the copies that are about to be overwritten or deleted
+        let (src_versions, dest_versions) = (VersionStore::new(src), VersionStore::new(dest));
+        let mut copy_jobs = Vec::new();
+        // files linked to another file of the destination once the copies are done, with the path they link to
+        let mut link_jobs = Vec::new();
+        let mut dirs = DirWork::default();
+        for ignored in plan.get_ignored() {
+            run.entry(PlanEntry::new(&entry_path(ignored.get_path(), ignored.is_dir()), EntryStatus::Ignored, ignored.get_size(), None, None));
+        }
+        for operation in plan.get_operations() {
+            let path = operation.get_path();
+            let (from, to, versions) = match operation.get_direction() {
+                SyncDirection::SrcToDest => (src, dest, &dest_versions),
+                SyncDirection::DestToSrc => (dest, src, &src_versions),
+            };
+            if check && operation.get_kind() != OperationKind::Conflict {
+                if let Err(e) = operation.check(src, dest, plan.get_links()) {
+                    run.refuse(path, format!("Refusing to sync {}: {}", path, e).as_str());
+                    continue;
+                }
+            }
+            let status = match operation.get_kind() {
+                OperationKind::Copy if operation.get_target_hash().is_some() => EntryStatus::Modified,
+                OperationKind::Copy => EntryStatus::New,
+                OperationKind::Delete => EntryStatus::Deleted,
+                OperationKind::Metadata => EntryStatus::Metadata,
+                OperationKind::Conflict => EntryStatus::Conflict,
+            };
+            let mut entry = PlanEntry::new(&entry_path(path, operation.is_dir()), status, operation.get_size(), operation.get_src_hash(), operation.get_dest_hash());
+            if plan.is_two_way() && status != EntryStatus::Conflict {
+                entry = entry.with_direction(&operation.get_direction().to_string());
+            }
+            match operation.get_kind() {
+                OperationKind::Copy if operation.is_dir() => {
+                    run.entry(entry);
+                    if !dry_run {
+                        if let Err(e) = fs::create_dir_all(to.join(path)) {
+                            run.error(Some(path), format!("Failed to create directory {}: {}", path, e).as_str());
+                            continue;
+                        }
+                    }
+                    dirs.attributes.push(DirAttributes::new(from, to, path, false));
+                    record_done(journal, path, run);
+                    run.summary.add_dirs_created(1);
+                }
+                OperationKind::Copy => {
+                    run.entry(entry);
+                    if backup {
+                        if let Err(e) = versions.backup(path) {
+                            run.error(Some(path), format!("Failed to back up {}: {}", path, e).as_str());
+                            continue;
+                        }
+                    }
+                    let job = CopyJob::new(from, to, path, operation.get_size())
+                        .with_expected_hash(operation.get_source_hash())
+                        .with_link(operation.is_link());
+                    match operation.get_hard_link() {
+                        Some(hard_link) => link_jobs.push((job, to.join(hard_link))),
+                        None => copy_jobs.push(job),
+                    }
+                }
+                OperationKind::Delete if operation.is_dir() => {
+                    run.entry(entry);
+                    dirs.removals.push((to.join(path), path.to_string()));
+                }
+                OperationKind::Delete => {
+                    run.entry(entry);
+                    if !dry_run {
+                        if let Err(e) = remove_file(to, path, Some(versions).filter(|_| backup)) {
+                            run.error(Some(path), format!("Failed to delete {}: {}", path, e).as_str());
+                            continue;
+                        }
+                    }
+                    record_done(journal, path, run);
+                    run.summary.add_deleted(1);
+                }
+                OperationKind::Metadata if operation.is_dir() => {
+                    run.entry(entry);
+                    dirs.attributes.push(DirAttributes::new(from, to, path, true));
+                }
+                OperationKind::Metadata => {
+                    run.entry(entry);
+                    if !dry_run {
+                        if let Err(e) = copy_attributes(&from.join(path), &to.join(path), plan.get_preserve()) {
+                            run.error(Some(path), format!("Failed to update the metadata of {}: {}", path, e).as_str());
+                            continue;
+                        }
+                    }
+                    record_done(journal, path, run);
+                    run.summary.add_updated(1);
+                }
+                OperationKind::Conflict => match resolver.as_mut() {
+                    Some(resolver) => {
+                        let src_file = FileTracker::open(&src.join(path).to_string_lossy(), plan.get_links()).ok();
+                        let dest_file = FileTracker::open(&dest.join(path).to_string_lossy(), plan.get_links()).ok();
+                        // the policy was picked for the versions that were scanned, not for an edit made since
+                        let changed = match (src_file.as_ref().map(|f| f.get_current_file_hash()), dest_file.as_ref().map(|f| f.get_current_file_hash())) {
+                            (src_hash, _) if src_hash != operation.get_src_hash() => Some("source"),
+                            (_, dest_hash) if dest_hash != operation.get_dest_hash() => Some("destination"),
+                            _ => None,
+                        };
+                        if let Some(side) = changed {
+                            run.refuse(path, format!("Refusing to resolve the conflict on {}: the {} changed since it was scanned", path, side).as_str());
+                            run.entry(entry);
+                            continue;
+                        }
+                        let policy = resolver.get_policy();
+                        let record = resolver.resolve(&operation.to_change(), src_file.as_ref(), dest_file.as_ref());
+                        let resolution = match record.get_resolution() {
+                            Ok(resolution) => resolution.to_string(),
+                            Err(e) => {
+                                run.error(Some(path), format!("Failed to resolve conflict on {}: {}", path, e).as_str());
+                                format!("failed: {}", e)
+                            }
+                        };
+                        run.observer.on_conflict(policy, &record);
+                        record_done(journal, path, run);
+                        run.entry(entry.with_resolution(&resolution));
+                    }
+                    None => run.entry(entry),
+                },
+            }
+        }
+
+        // copy the files, to implement dry run nothing is copied
+        match dry_run {
+            true => {
+                run.summary.add_copied(copy_jobs.len(), copy_jobs.iter().map(|job| job.get_size()).sum());
+                run.summary.add_hard_linked(link_jobs.len());
+            }
+            false => {
+                // the workers can not reach the observer, journal failures are reported once the copies are done
+                let journal_errors = Mutex::new(Vec::new());
+                let on_copied = |job: &CopyJob| {
+                    if let Some(Err(e)) = journal.map(|journal| journal.record(job.get_relative_path())) {
+                        journal_errors.lock().unwrap().push(format!("Failed to record {} in the journal: {}", job.get_relative_path(), e));
+                    }
+                };
+                let engine = self.copy_engine(plan.get_preserve());
+                let copy_report = engine.run_with(copy_jobs, on_copied, &mut *run.observer);
+                // the data the links share is in place now
+                let (linked, unlinked) = link_files(link_jobs, journal, run);
+                run.summary.add_hard_linked(linked);
+                let unlinked_report = engine.run_with(unlinked, on_copied, &mut *run.observer);
+                for e in journal_errors.into_inner().unwrap() {
+                    run.message(MessageKind::Warning, &e);
+                }
+                for report in [copy_report, unlinked_report] {
+                    report_copy_failures(run, &report);
+                    run.summary.add_copied(report.get_copied(), report.get_bytes());
+                    run.summary.add_verified(report.get_verified());
+                }
+            }
+        }
+        let (removed, updated) = finish_dirs(dirs, plan.get_preserve(), journal, run);
+        run.summary.add_dirs_removed(removed);
+        run.summary.add_updated(updated);
+    }
+
+    // copy (and in mirror mode remove) the given relative paths so the destination matches the source, then save its tracker
+    // with backups on, the files overwritten or deleted go to the version store like on any other sync
+    fn sync_watched_paths(&self, src_dir: &DirTracker, dest_dir: &mut DirTracker, mut paths: Vec<String>, run: &mut Run) {
+        paths.sort();
+        let (src, dest) = (Path::new(src_dir.get_path()), Path::new(dest_dir.get_path()));
+        let preserve = self.options.get_preserve();
+        let versions = VersionStore::new(dest);
+        let versions = Some(&versions).filter(|_| self.options.get_backup());
+        let mut summary = SyncSummary::new(false);
+        let mut copy_jobs = Vec::new();
+        let mut link_jobs = Vec::new();
+        let mut dirs = DirWork::default();
+        let mut touched = Vec::new();
+        for path in &paths {
+            if dest_dir.is_ignored(path) {
+                continue;
+            }
+            match (src_dir.get_file(path), dest_dir.get_file(path)) {
+                (Some(src_file), dest_file) if src_file.is_dir() => {
+                    let dest_hash = dest_file.map(|f| f.get_current_file_hash());
+                    let metadata_only = match dest_file {
+                        Some(dest_file) if dest_file.is_dir() => {
+                            if dest_file.has_same_metadata(src_file, preserve) {
+                                continue;
+                            }
+                            run.entry(PlanEntry::new(&entry_path(path, true), EntryStatus::Metadata, 0, Some(src_file.get_current_file_hash()), dest_hash));
+                            true
+                        }
+                        _ => {
+                            let status = if dest_file.is_some() { EntryStatus::Modified } else { EntryStatus::New };
+                            run.entry(PlanEntry::new(&entry_path(path, true), status, 0, Some(src_file.get_current_file_hash()), dest_hash));
+                            if let Err(e) = fs::create_dir_all(dest.join(path)) {
+                                run.error(Some(path), format!("Failed to create directory {}: {}", path, e).as_str());
+                                continue;
+                            }
+                            summary.add_dirs_created(1);
+                            false
+                        }
+                    };
+                    dirs.attributes.push(DirAttributes::new(src, dest, path, metadata_only));
+                }
+                (Some(src_file), dest_file) => {
+                    let dest_hash = dest_file.map(|f| f.get_current_file_hash());
+                    let status = match dest_file {
+                        // the same content, the attributes may still have changed
+                        Some(dest_file) if dest_file.get_current_file_hash() == src_file.get_current_file_hash() => {
+                            // the attributes of a link are never synced on their own, they would be set on the file it points to
+                            if src_file.is_link() || dest_file.has_same_metadata(src_file, preserve) {
+                                continue;
+                            }
+                            run.entry(PlanEntry::new(path, EntryStatus::Metadata, src_file.get_size(), Some(src_file.get_current_file_hash()), dest_hash));
+                            if let Err(e) = copy_attributes(&src.join(path), &dest.join(path), preserve) {
+                                run.error(Some(path), format!("Failed to update the metadata of {}: {}", path, e).as_str());
+                                continue;
+                            }
+                            summary.add_updated(1);
+                            touched.push(path.clone());
+                            continue;
+                        }
+                        Some(_) => EntryStatus::Modified,
+                        None => EntryStatus::New,
+                    };
+                    run.entry(PlanEntry::new(path, status, src_file.get_size(), Some(src_file.get_current_file_hash()), dest_hash));
+                    if let (Some(versions), EntryStatus::Modified) = (versions, status) {
+                        if let Err(e) = versions.backup(path) {
+                            run.error(Some(path), format!("Failed to back up {}: {}", path, e).as_str());
+                            continue;
+                        }
+                    }
+                    let job = CopyJob::new(src, dest, path, src_file.get_size())
+                        .with_expected_hash(Some(src_file.get_current_file_hash()))
+                        .with_link(src_file.is_link());
+                    match src_file.get_hard_link() {
+                        Some(hard_link) => link_jobs.push((job, dest.join(hard_link))),
+                        None => copy_jobs.push(job),
+                    }
+                }
+                (None, Some(dest_file)) if self.options.is_deleting() && dest_file.is_dir() => {
+                    run.entry(PlanEntry::new(&entry_path(path, true), EntryStatus::Deleted, 0, None, Some(dest_file.get_current_file_hash())));
+                    dirs.removals.push((dest.join(path), path.clone()));
+                }
+                (None, Some(dest_file)) if self.options.is_deleting() => {
+                    run.entry(PlanEntry::new(path, EntryStatus::Deleted, dest_file.get_size(), None, Some(dest_file.get_current_file_hash())));
+                    if let Err(e) = remove_file(dest, path, versions) {
+                        run.error(Some(path), format!("Failed to delete {}: {}", path, e).as_str());
+                        continue;
+                    }
+                    summary.add_deleted(1);
+                }
+                _ => continue,
+            }
+            touched.push(path.clone());
+        }
+        if touched.is_empty() {
+            return;
+        }
+        let engine = self.copy_engine(self.options.get_preserve());
+        let report = engine.run(copy_jobs, &mut *run.observer);
+        let (linked, unlinked) = link_files(link_jobs, None, run);
+        summary.add_hard_linked(linked);
+        let unlinked_report = engine.run(unlinked, &mut *run.observer);
+        for report in [report, unlinked_report] {
+            report_copy_failures(run, &report);
+            summary.add_copied(report.get_copied(), report.get_bytes());
+            summary.add_verified(report.get_verified());
+        }
+        let (removed, updated) = finish_dirs(dirs, preserve, None, run);
+        summary.add_dirs_removed(removed);
+        summary.add_updated(updated);
+        dest_dir.update_paths(&touched);
+        if let Err(e) = dest_dir.update_tracker() {
+            run.error(None, format!("Failed to update destination tracker: {}", e).as_str());
+        }
+        run.observer.on_watch_sync(&summary);
+    }
+}
+
+// the trackers of both sides of a plan that already ran, scanned with the ignore rules it was made with
+fn scan_planned_dirs(plan: &SyncPlan) -> Result<(DirTracker, DirTracker), SyncError> {
+    let mut src_dir = DirTracker::new(Path::new(plan.get_src()))?;
+    let mut dest_dir = DirTracker::new(Path::new(plan.get_dest()))?;
+    for pattern in plan.get_ignore() {
+        src_dir.add_ignore(pattern.clone());
+        dest_dir.add_ignore(pattern.clone());
+    }
+    src_dir.set_links(plan.get_links());
+    dest_dir.set_links(plan.get_links());
+    src_dir.set_cache_dir(Path::new(plan.get_dest()));
+    rayon::join(
+        || src_dir.import_files_from_directory(true),
+        || dest_dir.import_files_from_directory(true),
+    );
+    Ok((src_dir, dest_dir))
+}
+
+// files that could not be read are left out of the sync and reported with the other failures
+// links the link policy left out are only warned about
+fn report_scan_errors(run: &mut Run, dirs: &[&DirTracker]) {
+    for dir in dirs {
+        for e in dir.get_errors() {
+            match e.is_skipped_link() {
+                true => run.message(MessageKind::Warning, format!("Skipped link {}", e).as_str()),
+                false => run.error(Some(e.get_path()), format!("Skipped {}", e).as_str()),
+            }
+        }
+    }
+}
+
+fn report_copy_failures(run: &mut Run, report: &CopyReport) {
+    for (path, e) in report.get_failures() {
+        run.error(Some(path), format!("Failed to copy {}: {}", path, e).as_str());
+    }
+}
+
+// link each job to the file of the destination it shares its data with in the source
+// a job is handed back to be copied when that file does not hold the data the job expects or the filesystem has no hard links
+// returns how many links were made and the jobs left to copy
+fn link_files(jobs: Vec<(CopyJob, PathBuf)>, journal: Option<&SyncJournal>, run: &mut Run) -> (usize, Vec<CopyJob>) {
+    // every file of a group links to the same one, it is only read once
+    let mut hashes: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut linked = 0;
+    let mut unlinked = Vec::new();
+    for (job, existing) in jobs {
+        let hash = hashes.entry(existing.clone()).or_insert_with(|| FileTracker::hash_file(&existing).ok());
+        if hash.is_none() || hash.as_deref() != job.get_expected_hash() || hard_link_atomic(&existing, &job.get_target()).is_err() {
+            unlinked.push(job);
+            continue;
+        }
+        record_done(journal, job.get_relative_path(), run);
+        linked += 1;
+    }
+    (linked, unlinked)
+}
+
+impl DirAttributes {
+    fn new(from: &Path, to: &Path, path: &str, metadata_only: bool) -> Self {
+        DirAttributes {
+            from: from.join(path),
+            to: to.join(path),
+            path: path.to_string(),
+            metadata_only,
+        }
+    }
+}
+
+// remove the emptied directories, then set the attributes of the directories that were created or changed, deepest first
+// writing into a directory moves its mtime and a read only one could not be written into, so this runs after the files
+// returns how many directories were removed and how many only had their attributes updated
+fn finish_dirs(dirs: DirWork, preserve: PreserveMetadata, journal: Option<&SyncJournal>, run: &mut Run) -> (usize, usize) {
+    let dry_run = run.is_dry_run();
+    let (mut removed, mut updated) = (0, 0);
+    for (dir, path) in dirs.removals.into_iter().rev() {
+        if !dry_run {
+            match remove_dir(&dir) {
+                Ok(true) => {}
+                // files the sync does not touch, like ignored ones, keep their directory
+                Ok(false) => {
+                    run.message(MessageKind::Warning, format!("Kept directory {}, it still holds files that are not synced", path).as_str());
+                    continue;
+                }
+                Err(e) => {
+                    run.error(Some(&path), format!("Failed to remove directory {}: {}", path, e).as_str());
+                    continue;
+                }
+            }
+        }
+        record_done(journal, &path, run);
+        removed += 1;
+    }
+    for dir in dirs.attributes.into_iter().rev() {
+        if !dry_run {
+            if let Err(e) = copy_dir_attributes(&dir.from, &dir.to, preserve) {
+                run.error(Some(&dir.path), format!("Failed to update the metadata of {}: {}", dir.path, e).as_str());
+                continue;
+            }
+        }
+        if dir.metadata_only {
+            record_done(journal, &dir.path, run);
+            updated += 1;
+        }
+    }
+    (removed, updated)
+}
+
+// false when the directory is not empty, a directory that is already gone counts as removed
+fn remove_dir(dir: &Path) -> io::Result<bool> {
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    if entries.next().is_some() {
+        return Ok(false);
+    }
+    fs::remove_dir(dir).map(|_| true)
+}
+
+// directories are listed with a trailing slash
+fn entry_path(path: &str, dir: bool) -> String {
+    match dir {
+        true => format!("{}/", path),
+        false => path.to_string(),
+    }
+}
+
+// a record lost here only means the operation is checked against the disk again on resume
+fn record_done(journal: Option<&SyncJournal>, path: &str, run: &mut Run) {
+    if let Some(Err(e)) = journal.map(|journal| journal.record(path)) {
+        run.message(MessageKind::Warning, format!("Failed to record {} in the journal: {}", path, e).as_str());
+    }
+}
+
+// the journal is kept when operations failed, so `resume` can retry only those
+// `errors_before` is the number of errors reported before the plan ran, files the scan skipped are not in the plan
+fn finish_journal(journal: Option<SyncJournal>, run: &mut Run, errors_before: usize) {
+    let Some(journal) = journal else {
+        return;
+    };
+    if run.get_error_count() > errors_before {
+        run.message(MessageKind::Info, "Some operations failed, run the sync again with --resume to retry them");
+        return;
+    }
+    if let Err(e) = journal.finish() {
+        run.error(None, format!("Failed to remove the journal: {}", e).as_str());
+    }
+}
+
+// copies that were cut off leave temp files next to their target, clear them before syncing again
+// `temp_files` are the ones the scan of `dir` found
+fn remove_temp_files(run: &mut Run, dir: &Path, temp_files: &[PathBuf]) {
+    let removed = temp_files.iter().filter(|temp| fs::remove_file(temp).is_ok()).count();
+    if removed > 0 {
+        run.message(MessageKind::Info, format!("Removed {} temp files left by an interrupted sync in {:?}", removed, dir).as_str());
+    }
+}
+
+// without a scan, only the temp files next to the targets of the plan can be the sync's own
+fn remove_planned_temp_files(run: &mut Run, plan: &SyncPlan) {
+    let (src, dest) = (Path::new(plan.get_src()), Path::new(plan.get_dest()));
+    for (dir, direction) in [(dest, SyncDirection::SrcToDest), (src, SyncDirection::DestToSrc)] {
+        let temp_files: Vec<PathBuf> = plan.get_operations()
+            .iter()
+            .filter(|op| op.get_direction() == direction)
+            .filter_map(|op| temp_path(&dir.join(op.get_path())).ok())
+            .collect();
+        remove_temp_files(run, dir, &temp_files);
+    }
+}
+
+// save the new common state so the next run only sees changes made after this one
+fn save_sync_states(src_dir: &mut DirTracker, dest_dir: &mut DirTracker, run: &mut Run) {
+    if let Err(e) = dest_dir.save_sync_state() {
+        run.error(None, format!("Failed to update destination tracker: {}", e).as_str());
+    }
+    match src_dir.dir_initialized() {
+        Ok(()) => {
+            if let Err(e) = src_dir.save_sync_state() {
+                run.error(None, format!("Failed to update source tracker: {}", e).as_str());
+            }
+        }
+        // a one way sync only reads the source, the hashes of its scan are still good
+        Err(_) => {
+            if let Err(e) = src_dir.save_cache() {
+                run.error(None, format!("Failed to save the source hashes: {}", e).as_str());
+            }
+        }
+    }
+}
+
+// delete a file, or move it into the version store when backups are on
+fn remove_file(dir: &Path, relative_path: &str, versions: Option<&VersionStore>) -> std::io::Result<()> {
+    match versions {
+        Some(versions) => versions.backup_and_remove(relative_path).map(|_| ()),
+        None => fs::remove_file(dir.join(relative_path)),
+    }
+}
+
+fn prune_versions(run: &mut Run, versions: &VersionStore, retention: Option<RetentionPolicy>) {
+    let Some(retention) = retention else {
+        return;
+    };
+    match versions.prune(retention) {
+        Ok(0) => {}
+        Ok(removed) => run.message(MessageKind::Info, format!("Removed {} old backup runs", removed).as_str()),
+        Err(e) => run.message(MessageKind::Warning, format!("Failed to prune backups: {}", e).as_str()),
+    }
+}
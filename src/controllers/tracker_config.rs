@@ -1,6 +1,271 @@
+use std::{collections::HashMap, env, fs, path::{Path, PathBuf}};
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// how a profile syncs its directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncMode {
+    #[default]
+    OneWay,
+    // one way, and files deleted from the source are deleted from the destination
+    Mirror,
+    TwoWay,
+}
+
+// how copied files are checked against the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyLevel {
+    #[default]
+    None,
+    Size,
+    Hash,
+}
+
+// what a sync does with symbolic links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkPolicy {
+    // recreate the link itself, pointing at the same target
+    Copy,
+    // sync what the link points to as if it were there
+    Follow,
+    // leave links out, with a warning for each
+    #[default]
+    Skip,
+}
+
+impl LinkPolicy {
+    pub fn from_name(name: &str) -> Result<LinkPolicy, String> {
+        match name.trim().to_lowercase().as_str() {
+            "copy" => Ok(LinkPolicy::Copy),
+            "follow" => Ok(LinkPolicy::Follow),
+            "skip" => Ok(LinkPolicy::Skip),
+            _ => Err(format!("Unknown link policy {:?}, expected one of copy, follow, skip", name)),
+        }
+    }
+}
+
+// which attributes of a source file are carried over to the destination, like rsync's --archive
+// permission bits are always copied along with the content, `permissions` also syncs files whose mode alone changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreserveMetadata {
+    // modification and access times
+    times: bool,
+    permissions: bool,
+    // uid and gid, only possible when running as root
+    owner: bool,
+    xattrs: bool,
+}
+
+impl PreserveMetadata {
+    // times, permissions and, when running as root, the owner
+    pub fn archive() -> Self {
+        PreserveMetadata {
+            times: true,
+            permissions: true,
+            owner: is_root(),
+            xattrs: false,
+        }
+    }
+
+    pub fn with_xattrs(mut self, xattrs: bool) -> Self {
+        self.xattrs = xattrs;
+        self
+    }
+
+    pub fn get_times(&self) -> bool {
+        self.times
+    }
+
+    pub fn get_permissions(&self) -> bool {
+        self.permissions
+    }
+
+    pub fn get_owner(&self) -> bool {
+        self.owner
+    }
+
+    pub fn get_xattrs(&self) -> bool {
+        self.xattrs
+    }
+
+    pub fn is_any(&self) -> bool {
+        self.times || self.permissions || self.owner || self.xattrs
+    }
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and can not fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+// shell commands run before and after a profile syncs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncHooks {
+    pre_sync: Vec<String>,
+    post_sync: Vec<String>,
+}
+
+impl SyncHooks {
+    pub fn get_pre_sync(&self) -> &[String] {
+        &self.pre_sync
+    }
+
+    pub fn get_post_sync(&self) -> &[String] {
+        &self.post_sync
+    }
+}
+
+// a named set of sync settings, anything left out falls back to the command line flags
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncProfile {
+    src: Option<String>,
+    dest: Option<String>,
+    dest_label: Option<String>,
+    exclude: Vec<String>,
+    mode: SyncMode,
+    conflict: Option<String>,
+    verify: VerifyLevel,
+    backup: bool,
+    // keep times, permissions and ownership like --archive
+    archive: bool,
+    xattrs: bool,
+    links: LinkPolicy,
+    jobs: Option<usize>,
+    hooks: SyncHooks,
+}
+
+impl SyncProfile {
+    pub fn get_src(&self) -> Option<&String> {
+        self.src.as_ref()
+    }
+
+    pub fn get_dest(&self) -> Option<&String> {
+        self.dest.as_ref()
+    }
+
+    pub fn get_dest_label(&self) -> Option<&String> {
+        self.dest_label.as_ref()
+    }
+
+    pub fn get_exclude(&self) -> &[String] {
+        &self.exclude
+    }
+
+    pub fn get_mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn get_conflict(&self) -> Option<&String> {
+        self.conflict.as_ref()
+    }
+
+    pub fn get_verify(&self) -> VerifyLevel {
+        self.verify
+    }
+
+    pub fn get_backup(&self) -> bool {
+        self.backup
+    }
+
+    pub fn get_archive(&self) -> bool {
+        self.archive
+    }
+
+    pub fn get_xattrs(&self) -> bool {
+        self.xattrs
+    }
+
+    pub fn get_links(&self) -> LinkPolicy {
+        self.links
+    }
+
+    pub fn get_jobs(&self) -> Option<usize> {
+        self.jobs
+    }
+
+    pub fn get_hooks(&self) -> &SyncHooks {
+        &self.hooks
+    }
+}
+
+// the user configuration, read from the user config directory and from .hard_sync_cli/config.toml in the synced directories
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TrackerConfig {
+    #[serde(default)]
+    profiles: HashMap<String, SyncProfile>,
+}
+
+impl TrackerConfig {
+    // merge every config file that exists, later files override profiles with the same name
+    pub fn load(roots: &[PathBuf]) -> Result<TrackerConfig, String> {
+        let mut config = TrackerConfig::default();
+        for path in Self::get_config_paths(roots) {
+            if path.is_file() {
+                config.merge(Self::load_file(&path)?);
+            }
+        }
+        Ok(config)
+    }
+
+    // a .toml or .json config file
+    pub fn load_file(path: &Path) -> Result<TrackerConfig, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e)),
+            _ => toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    // the user config first, then the .hard_sync_cli of each synced directory in `roots`, usually the source and then the destination
+    pub fn get_config_paths(roots: &[PathBuf]) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(config_dir) = user_config_dir() {
+            dirs.push(config_dir.join("hard-sync"));
+        }
+        for root in roots {
+            let dir = root.join(".hard_sync_cli");
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs.iter()
+            .flat_map(|dir| [dir.join("config.toml"), dir.join("config.json")])
+            .collect()
+    }
+
+    pub fn get_profile(&self, name: &str) -> Option<&SyncProfile> {
+        self.profiles.get(name)
+    }
+
+    pub fn get_profiles(&self) -> &HashMap<String, SyncProfile> {
+        &self.profiles
+    }
+
+    fn merge(&mut self, other: TrackerConfig) {
+        self.profiles.extend(other.profiles);
+    }
+}
 
-}
\ No newline at end of file
+fn user_config_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+        if !dir.is_empty() {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    if let Ok(dir) = env::var("APPDATA") {
+        return Some(PathBuf::from(dir));
+    }
+    env::var("HOME").ok().map(|home| Path::new(&home).join(".config"))
+}
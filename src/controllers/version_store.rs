@@ -0,0 +1,168 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use chrono::{Duration, Local, NaiveDateTime, TimeZone};
+
+use super::tracker_config::PreserveMetadata;
+use crate::helpers::file_ops::{copy_file_atomic, copy_link_atomic, temp_path};
+
+const VERSIONS_DIR: &str = "versions";
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.3f";
+
+// how long stored versions are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    KeepLast(usize),
+    KeepDays(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileVersion {
+    timestamp: String,
+    path: PathBuf,
+    size: u64,
+}
+
+impl FileVersion {
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+// keeps the old copies of overwritten and deleted files in .hard_sync_cli/versions/<timestamp>/<relative path>
+pub struct VersionStore {
+    dir: PathBuf,
+    timestamp: String,
+}
+
+impl VersionStore {
+    // `dir` is the synced directory, every backup made through this store goes under the same timestamp
+    pub fn new(dir: &Path) -> Self {
+        VersionStore {
+            dir: dir.to_path_buf(),
+            timestamp: Local::now().format(TIMESTAMP_FORMAT).to_string(),
+        }
+    }
+
+    pub fn get_versions_dir(&self) -> PathBuf {
+        self.dir.join(".hard_sync_cli").join(VERSIONS_DIR)
+    }
+
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    // store the current file before it is overwritten, it stays in place until its replacement is renamed over it
+    // so a copy that fails or is interrupted leaves it untouched. the stored version is a hard link where the filesystem
+    // has them, which costs no space, and a copy otherwise. returns false when there was nothing to back up
+    pub fn backup(&self, relative_path: &str) -> io::Result<bool> {
+        let current = self.dir.join(relative_path);
+        let Some(version) = self.version_path(relative_path)? else {
+            return Ok(false);
+        };
+        // a link is stored as it is, whatever it points to
+        if current.is_symlink() {
+            copy_link_atomic(&current, &version, None, PreserveMetadata::default())?;
+        } else if fs::hard_link(&current, &version).is_err() {
+            copy_file_atomic(&current, &version, None, PreserveMetadata::archive(), |_| {})?;
+        }
+        Ok(true)
+    }
+
+    // move the current file into the store, for a file that is deleted
+    // returns false when there was nothing to back up
+    pub fn backup_and_remove(&self, relative_path: &str) -> io::Result<bool> {
+        let Some(version) = self.version_path(relative_path)? else {
+            return Ok(false);
+        };
+        fs::rename(self.dir.join(relative_path), version)?;
+        Ok(true)
+    }
+
+    // where the current file is stored by this run, None when there is no file (or only a directory) to store
+    fn version_path(&self, relative_path: &str) -> io::Result<Option<PathBuf>> {
+        if !fs::symlink_metadata(self.dir.join(relative_path)).is_ok_and(|metadata| !metadata.is_dir()) {
+            return Ok(None);
+        }
+        let version = self.get_versions_dir().join(&self.timestamp).join(relative_path);
+        if let Some(parent) = version.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Some(version))
+    }
+
+    // all stored versions of a file, oldest first
+    pub fn list_versions(&self, relative_path: &str) -> io::Result<Vec<FileVersion>> {
+        let mut versions = Vec::new();
+        for timestamp in self.list_timestamps()? {
+            let path = self.get_versions_dir().join(&timestamp).join(relative_path);
+            if let Ok(metadata) = fs::metadata(&path) {
+                if metadata.is_file() {
+                    versions.push(FileVersion { timestamp, path, size: metadata.len() });
+                }
+            }
+        }
+        Ok(versions)
+    }
+
+    // put a stored version back in place, the current file is backed up first so the restore can be undone
+    pub fn restore(&self, relative_path: &str, timestamp: &str) -> Result<(), String> {
+        let version = self.get_versions_dir().join(timestamp).join(relative_path);
+        if !version.is_file() {
+            return Err(format!("No version of {} from {}", relative_path, timestamp));
+        }
+        let target = self.dir.join(relative_path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        // stage the old version next to the target before the current file is stored
+        let staged = temp_path(&target).map_err(|e| e.to_string())?;
+        fs::copy(version, &staged).map_err(|e| e.to_string())?;
+        self.backup(relative_path).map_err(|e| e.to_string())?;
+        fs::rename(staged, target).map_err(|e| e.to_string())
+    }
+
+    // remove the backup runs the retention policy no longer covers, returns how many were removed
+    pub fn prune(&self, retention: RetentionPolicy) -> io::Result<usize> {
+        let timestamps = self.list_timestamps()?;
+        let expired: Vec<&String> = match retention {
+            RetentionPolicy::KeepLast(count) => timestamps.iter().rev().skip(count).collect(),
+            RetentionPolicy::KeepDays(days) => {
+                let cutoff = Local::now() - Duration::days(days);
+                timestamps
+                    .iter()
+                    .filter(|t| match NaiveDateTime::parse_from_str(t, TIMESTAMP_FORMAT) {
+                        Ok(time) => Local.from_local_datetime(&time).earliest().is_some_and(|time| time < cutoff),
+                        Err(_) => false,
+                    })
+                    .collect()
+            }
+        };
+        for timestamp in &expired {
+            fs::remove_dir_all(self.get_versions_dir().join(timestamp))?;
+        }
+        Ok(expired.len())
+    }
+
+    // the timestamp directories, oldest first
+    fn list_timestamps(&self) -> io::Result<Vec<String>> {
+        let versions_dir = self.get_versions_dir();
+        if !versions_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut timestamps: Vec<String> = fs::read_dir(versions_dir)?
+            .filter_map(Result::ok)
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().to_str().map(|s| s.to_string()))
+            .collect();
+        timestamps.sort();
+        Ok(timestamps)
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fmt, io, path::Path};
+
+// what can go wrong while scanning or syncing a directory
+// errors about a single file are collected and reported at the end, the others stop the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    // reading or writing a path failed: permission denied, vanished mid scan, disk full
+    Io { path: String, kind: io::ErrorKind, message: String },
+    // a name that is not valid utf-8 and can not be stored in tracker.json
+    InvalidName { path: String },
+    // a symbolic link the link policy left out, reported as a warning
+    SkippedLink { path: String, reason: String },
+    NotFound { path: String },
+    NotADirectory { path: String },
+    NotInitialized { path: String },
+    AlreadyInitialized { path: String },
+    // tracker.json, a plan or a journal that could not be read or written
+    InvalidState { path: String, message: String },
+    // the directory being watched could not be watched, or the watch stopped
+    Watch { path: String, message: String },
+    // options that do not work together, like resuming a sync as a dry run
+    InvalidOptions { message: String },
+}
+
+impl SyncError {
+    pub fn io(path: &Path, e: io::Error) -> Self {
+        SyncError::Io {
+            path: path.to_string_lossy().to_string(),
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+
+    pub fn invalid_state(path: &Path, message: impl fmt::Display) -> Self {
+        SyncError::InvalidState {
+            path: path.to_string_lossy().to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_options(message: impl Into<String>) -> Self {
+        SyncError::InvalidOptions { message: message.into() }
+    }
+
+    pub fn invalid_name(path: &Path) -> Self {
+        SyncError::InvalidName { path: path.to_string_lossy().to_string() }
+    }
+
+    pub fn get_path(&self) -> &str {
+        match self {
+            SyncError::Io { path, .. }
+            | SyncError::InvalidName { path }
+            | SyncError::SkippedLink { path, .. }
+            | SyncError::NotFound { path }
+            | SyncError::NotADirectory { path }
+            | SyncError::NotInitialized { path }
+            | SyncError::AlreadyInitialized { path }
+            | SyncError::InvalidState { path, .. }
+            | SyncError::Watch { path, .. } => path,
+            SyncError::InvalidOptions { .. } => "",
+        }
+    }
+
+    // the run can go on without the file, it is reported with the other failures at the end
+    // a skipped link is left out on purpose, so it is not a failure and does not count as unreadable
+    pub fn is_file_error(&self) -> bool {
+        matches!(self, SyncError::Io { .. } | SyncError::InvalidName { .. })
+    }
+
+    pub fn is_skipped_link(&self) -> bool {
+        matches!(self, SyncError::SkippedLink { .. })
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::Io { path, message, .. } => write!(f, "{}: {}", path, message),
+            SyncError::InvalidName { path } => write!(f, "{}: the name is not valid UTF-8", path),
+            SyncError::SkippedLink { path, reason } => write!(f, "{}: {}", path, reason),
+            SyncError::NotFound { path } => write!(f, "{} does not exist", path),
+            SyncError::NotADirectory { path } => write!(f, "{} is not a directory", path),
+            SyncError::NotInitialized { path } => write!(f, "{} is not initialized", path),
+            SyncError::AlreadyInitialized { path } => write!(f, "{} is already initialized", path),
+            SyncError::InvalidState { path, message } => write!(f, "{}: {}", path, message),
+            SyncError::Watch { path, message } => write!(f, "Could not watch {}: {}", path, message),
+            SyncError::InvalidOptions { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+// the cli still reports errors as text, so `?` turns them into one
+impl From<SyncError> for String {
+    fn from(e: SyncError) -> String {
+        e.to_string()
+    }
+}
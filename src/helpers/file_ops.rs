@@ -0,0 +1,271 @@
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}};
+
+use filetime::FileTime;
+use sha2::{Digest, Sha256};
+
+use crate::controllers::file_tracker::FileTracker;
+use crate::controllers::tracker_config::PreserveMetadata;
+
+// every temp file written next to its final path ends with this, so leftovers from a crashed run can be found
+pub const TEMP_SUFFIX: &str = ".hsync-tmp";
+
+// `.name.hsync-tmp` in the same directory as `path`, so the final rename never crosses filesystems
+pub fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    Ok(path.with_file_name(format!(".{}{}", file_name.to_string_lossy(), TEMP_SUFFIX)))
+}
+
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with('.') && name.ends_with(TEMP_SUFFIX))
+        .unwrap_or(false)
+}
+
+// write `content` to a temp file next to `path`, flush it to disk, then rename it over `path`
+pub fn write_file_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_parent_dir(path);
+    Ok(())
+}
+
+const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+// copy a file in chunks, calling `on_progress` with the number of bytes written after each chunk
+// the data goes into a temp file that is fsynced, checked against `expected_hash` and only then renamed into place,
+// so an interrupted copy never leaves a truncated file under the real name. the attributes `preserve` keeps are set on the temp file too
+pub fn copy_file_atomic(from: &Path, to: &Path, expected_hash: Option<&str>, preserve: PreserveMetadata, on_progress: impl FnMut(u64)) -> io::Result<u64> {
+    copy_file_checked(from, to, expected_hash, preserve, on_progress, |_, _| Ok::<(), io::Error>(()))?
+}
+
+// `copy_file_atomic` that also has `check` look at the fsynced temp file and the bytes copied before the rename
+// the outer error is the copy failing, the inner one `check` refusing the copy. either way `to` is left as it was
+pub fn copy_file_checked<E>(from: &Path, to: &Path, expected_hash: Option<&str>, preserve: PreserveMetadata, mut on_progress: impl FnMut(u64), check: impl FnOnce(&Path, u64) -> Result<(), E>) -> io::Result<Result<u64, E>> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = temp_path(to)?;
+    let result = (|| {
+        let mut reader = fs::File::open(from)?;
+        // read before the copy does, which moves the access time
+        let metadata = reader.metadata()?;
+        let mut writer = fs::File::create(&temp)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+        let mut copied = 0;
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..read])?;
+            hasher.update(&buffer[..read]);
+            copied += read as u64;
+            on_progress(read as u64);
+        }
+        // keep the permission bits like fs::copy does
+        writer.set_permissions(metadata.permissions())?;
+        writer.sync_all()?;
+        if let Some(expected_hash) = expected_hash {
+            if format!("{:x}", hasher.finalize()) != expected_hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "copied data does not match the source hash, the source changed during the sync",
+                ));
+            }
+        }
+        set_attributes(from, &metadata, &temp, preserve)?;
+        if let Err(e) = check(&temp, copied) {
+            return Ok(Err(e));
+        }
+        fs::rename(&temp, to)?;
+        Ok(Ok(copied))
+    })();
+    if !matches!(result, Ok(Ok(_))) {
+        let _ = fs::remove_file(&temp);
+    }
+    let copied = result?;
+    if copied.is_ok() {
+        sync_parent_dir(to);
+    }
+    Ok(copied)
+}
+
+// recreate the link at `from` as a link at `to` with the same target, through a temp link renamed into place
+// `expected_hash` is the hash the link had when it was scanned. returns the length of the target
+pub fn copy_link_atomic(from: &Path, to: &Path, expected_hash: Option<&str>, preserve: PreserveMetadata) -> io::Result<u64> {
+    let target = fs::read_link(from)?;
+    if expected_hash.is_some_and(|hash| hash != FileTracker::hash_link(&target)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the link was pointed elsewhere during the sync"));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = temp_path(to)?;
+    let _ = fs::remove_file(&temp);
+    let result = (|| {
+        create_link(&target, from, &temp)?;
+        set_link_attributes(from, &temp, preserve)?;
+        fs::rename(&temp, to)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_parent_dir(to);
+    Ok(target.as_os_str().len() as u64)
+}
+
+// make `to` another name for `existing`, which already holds the data the copy would write, through a temp link and a rename
+// fails where the filesystem has no hard links, the file then has to be copied instead
+pub fn hard_link_atomic(existing: &Path, to: &Path) -> io::Result<()> {
+    // renaming a link over another name of the same file does nothing and would leave the temp link behind
+    if is_same_file(existing, to) {
+        return Ok(());
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = temp_path(to)?;
+    let _ = fs::remove_file(&temp);
+    let result = fs::hard_link(existing, &temp).and_then(|_| fs::rename(&temp, to));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_parent_dir(to);
+    Ok(())
+}
+
+// whether both paths are names of the same file, always false where that can not be told
+#[cfg(unix)]
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn create_link(target: &Path, _from: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+// windows has separate links for files and directories, a link to nothing is made a file link
+#[cfg(windows)]
+fn create_link(target: &Path, from: &Path, link: &Path) -> io::Result<()> {
+    match fs::metadata(from).map(|m| m.is_dir()).unwrap_or(false) {
+        true => std::os::windows::fs::symlink_dir(target, link),
+        false => std::os::windows::fs::symlink_file(target, link),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_link(_target: &Path, _from: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported on this platform"))
+}
+
+// a link has no permissions of its own, only its owner and times are kept
+fn set_link_attributes(from: &Path, to: &Path, preserve: PreserveMetadata) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    #[cfg(unix)]
+    if preserve.get_owner() {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::lchown(to, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    if preserve.get_times() {
+        filetime::set_symlink_file_times(to, FileTime::from_last_access_time(&metadata), FileTime::from_last_modification_time(&metadata))?;
+    }
+    Ok(())
+}
+
+// copy the attributes `preserve` keeps from `from` onto `to`, for files whose content is already the same
+pub fn copy_attributes(from: &Path, to: &Path, preserve: PreserveMetadata) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    if preserve.get_permissions() {
+        fs::set_permissions(to, metadata.permissions())?;
+    }
+    set_attributes(from, &metadata, to, preserve)
+}
+
+// give a directory the permission bits of `from`, like a copied file gets, and the attributes `preserve` keeps
+// only called once nothing more is written into it, so a read only directory or a kept mtime stays that way
+pub fn copy_dir_attributes(from: &Path, to: &Path, preserve: PreserveMetadata) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    fs::set_permissions(to, metadata.permissions())?;
+    set_attributes(from, &metadata, to, preserve)
+}
+
+// the times go last, changing the owner or the xattrs would not move them but writing the file would
+fn set_attributes(from: &Path, metadata: &fs::Metadata, to: &Path, preserve: PreserveMetadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if preserve.get_owner() {
+            std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid()))?;
+            // changing the owner clears the setuid and setgid bits
+            fs::set_permissions(to, metadata.permissions())?;
+        }
+        if preserve.get_xattrs() {
+            for name in xattr::list(from)? {
+                if let Some(value) = xattr::get(from, &name)? {
+                    xattr::set(to, &name, &value)?;
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = from;
+    if preserve.get_times() {
+        filetime::set_file_times(to, FileTime::from_last_access_time(metadata), FileTime::from_last_modification_time(metadata))?;
+    }
+    Ok(())
+}
+
+// ask the kernel to forget the cached pages of a file that was fsynced, so the next read comes from the disk
+// only linux, android and freebsd have posix_fadvise, elsewhere a read back may still be served from memory
+pub fn drop_cached_pages(path: &Path) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        let file = fs::File::open(path)?;
+        // dirty pages are left alone, the copy was fsynced so all of them are clean
+        let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    let _ = path;
+    Ok(())
+}
+
+// the rename is only durable once the directory entry is flushed too, not supported on every platform
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
@@ -0,0 +1,27 @@
+use std::{path::Path, process::Command};
+
+// run a profile hook through the shell, the synced directories are passed as HSYNC_SRC and HSYNC_DEST
+pub fn run_hook(command: &str, src: &Path, dest: &Path) -> Result<(), String> {
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    let status = shell
+        .arg(command)
+        .env("HSYNC_SRC", src)
+        .env("HSYNC_DEST", dest)
+        .status()
+        .map_err(|e| format!("Could not run hook {:?}: {}", command, e))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("Hook {:?} failed with {}", command, status)),
+    }
+}
@@ -1,7 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use colored::Colorize;
 
+// set while stdout is reserved for json output, the log lines then go to stderr
+static MACHINE_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_machine_output(machine_output: bool) {
+    MACHINE_OUTPUT.store(machine_output, Ordering::Relaxed);
+}
+
+pub fn is_machine_output() -> bool {
+    MACHINE_OUTPUT.load(Ordering::Relaxed)
+}
+
 pub fn print(message: &str) {
-    println!("{}", message);
+    match is_machine_output() {
+        true => eprintln!("{}", message),
+        false => println!("{}", message),
+    }
 }
 
 pub fn print_error(message: &str) {
@@ -21,5 +37,5 @@ pub fn print_warning(message: &str) {
 }
 
 pub fn step_message(step: &str, message: &str) {
-    println!("{}", format!("Step: {}", message).cyan());
-}
\ No newline at end of file
+    print(format!("Step {}: {}", step, message).cyan().to_string().as_str());
+}
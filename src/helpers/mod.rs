@@ -1 +1,6 @@
-pub mod logger;
\ No newline at end of file
+pub mod logger;
+pub mod file_ops;
+pub mod progress;
+pub mod hooks;
+pub mod output;
+pub mod error;
\ No newline at end of file
@@ -0,0 +1,440 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use serde::Serialize;
+
+use super::logger::{print_error, print_info, print_success, print_warning, set_machine_output};
+use super::progress::ProgressDisplay;
+use crate::controllers::conflict::{ConflictPolicy, ConflictRecord};
+use crate::controllers::sync_observer::{MessageKind, SyncObserver};
+use crate::controllers::sync_plan::SyncPlan;
+
+// how the sync reports its plan and results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    // a single json document printed when the sync finishes
+    Json,
+    // one json object per line, printed as the sync goes
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat, String> {
+        match name.trim().to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("Unknown output format {}, expected text, json or ndjson", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EntryStatus {
+    New,
+    Modified,
+    Deleted,
+    // only the times, permissions or owner changed
+    Metadata,
+    Ignored,
+    Conflict,
+}
+
+impl fmt::Display for EntryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EntryStatus::New => "New",
+            EntryStatus::Modified => "Modified",
+            EntryStatus::Deleted => "Deleted",
+            EntryStatus::Metadata => "Metadata",
+            EntryStatus::Ignored => "Ignored",
+            EntryStatus::Conflict => "Conflict",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// one file of the sync plan
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanEntry {
+    path: String,
+    status: EntryStatus,
+    size: u64,
+    src_hash: Option<String>,
+    dest_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolution: Option<String>,
+}
+
+impl PlanEntry {
+    pub fn new(path: &str, status: EntryStatus, size: u64, src_hash: Option<&str>, dest_hash: Option<&str>) -> Self {
+        PlanEntry {
+            path: path.to_string(),
+            status,
+            size,
+            src_hash: src_hash.map(str::to_string),
+            dest_hash: dest_hash.map(str::to_string),
+            direction: None,
+            resolution: None,
+        }
+    }
+
+    // which way the file goes in a two way sync
+    pub fn with_direction(mut self, direction: &str) -> Self {
+        self.direction = Some(direction.to_string());
+        self
+    }
+
+    // what the conflict policy did with a conflicting file
+    pub fn with_resolution(mut self, resolution: &str) -> Self {
+        self.resolution = Some(resolution.to_string());
+        self
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_status(&self) -> EntryStatus {
+        self.status
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    path: Option<String>,
+    message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncSummary {
+    dry_run: bool,
+    copied: usize,
+    // files linked to data already in place instead of copied, they are not counted as copied
+    hard_linked: usize,
+    deleted: usize,
+    // files whose attributes were updated without copying the content
+    updated: usize,
+    dirs_created: usize,
+    // emptied directories removed in mirror and two way syncs
+    dirs_removed: usize,
+    ignored: usize,
+    conflicts: usize,
+    errors: usize,
+    // copied files read back and found to match the source
+    verified: usize,
+    bytes: u64,
+    duration_ms: u128,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a> {
+    Entry(&'a PlanEntry),
+    Error(&'a ErrorRecord),
+    Summary(&'a SyncSummary),
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    plan: &'a [PlanEntry],
+    errors: &'a [ErrorRecord],
+    summary: &'a SyncSummary,
+}
+
+// collects the plan, errors and totals of a sync and prints them in the chosen format
+// in the json formats the regular log lines go to stderr so stdout only holds json
+pub struct OutputWriter {
+    format: OutputFormat,
+    started: Instant,
+    entries: Vec<PlanEntry>,
+    errors: Vec<ErrorRecord>,
+    summary: SyncSummary,
+    conflicts: Vec<ConflictRecord>,
+    conflict_policy: Option<ConflictPolicy>,
+    // set while files are being copied
+    progress: Option<ProgressDisplay>,
+}
+
+impl OutputWriter {
+    pub fn new(format: OutputFormat, dry_run: bool) -> Self {
+        set_machine_output(format != OutputFormat::Text);
+        OutputWriter {
+            format,
+            started: Instant::now(),
+            entries: Vec::new(),
+            errors: Vec::new(),
+            summary: SyncSummary::new(dry_run),
+            conflicts: Vec::new(),
+            conflict_policy: None,
+            progress: None,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn entry(&mut self, entry: PlanEntry) {
+        match self.format {
+            OutputFormat::Text => {
+                let status = match entry.status {
+                    EntryStatus::New => entry.status.to_string().green(),
+                    EntryStatus::Modified => entry.status.to_string().yellow(),
+                    EntryStatus::Deleted => entry.status.to_string().red(),
+                    EntryStatus::Metadata => entry.status.to_string().cyan(),
+                    EntryStatus::Ignored => entry.status.to_string().dimmed(),
+                    EntryStatus::Conflict => entry.status.to_string().magenta(),
+                };
+                match &entry.direction {
+                    Some(direction) => println!("{} ({}) {}", entry.path, status.underline(), direction),
+                    None => println!("{} ({})", entry.path, status.underline()),
+                }
+            }
+            OutputFormat::Ndjson => print_json_line(&Record::Entry(&entry)),
+            // only the json document lists the plan at the end, a long watch would otherwise keep every entry
+            OutputFormat::Json => self.entries.push(entry),
+        }
+    }
+
+    // `message` is the full sentence shown in text mode, `path` the file it is about if any
+    pub fn error(&mut self, path: Option<&str>, message: &str) {
+        let record = ErrorRecord {
+            path: path.map(str::to_string),
+            message: message.to_string(),
+        };
+        match self.format {
+            OutputFormat::Text => print_error(message),
+            OutputFormat::Ndjson => print_json_line(&Record::Error(&record)),
+            OutputFormat::Json => {}
+        }
+        self.errors.push(record);
+    }
+
+    // the totals returned by the sync, printed with the plan
+    pub fn set_summary(&mut self, summary: SyncSummary) {
+        self.summary = summary;
+    }
+
+    pub fn get_summary(&self) -> &SyncSummary {
+        &self.summary
+    }
+
+    // the conflicts met by the sync and the policy they were resolved with
+    pub fn get_conflicts(&self) -> &[ConflictRecord] {
+        &self.conflicts
+    }
+
+    pub fn get_conflict_policy(&self) -> Option<ConflictPolicy> {
+        self.conflict_policy
+    }
+
+    // print the summary object, the text summary is printed by the caller
+    pub fn finish(&mut self) {
+        self.summary.errors = self.errors.len();
+        self.summary.duration_ms = self.started.elapsed().as_millis();
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Ndjson => print_json_line(&Record::Summary(&self.summary)),
+            OutputFormat::Json => {
+                let document = Document {
+                    plan: &self.entries,
+                    errors: &self.errors,
+                    summary: &self.summary,
+                };
+                println!("{}", serde_json::to_string_pretty(&document).unwrap());
+            }
+        }
+    }
+}
+
+impl SyncObserver for OutputWriter {
+    fn on_plan(&mut self, plan: &SyncPlan) {
+        print_info(format!("All ignored files patterns: {:?}", plan.get_ignore()).as_str());
+    }
+
+    fn on_entry(&mut self, entry: &PlanEntry) {
+        self.entry(entry.clone());
+    }
+
+    fn on_error(&mut self, path: Option<&str>, message: &str) {
+        self.error(path, message);
+    }
+
+    fn on_message(&mut self, kind: MessageKind, message: &str) {
+        match kind {
+            MessageKind::Info => print_info(message),
+            MessageKind::Success => print_success(message),
+            MessageKind::Warning => print_warning(message),
+        }
+    }
+
+    fn on_conflict(&mut self, policy: ConflictPolicy, record: &ConflictRecord) {
+        self.conflict_policy = Some(policy);
+        self.conflicts.push(record.clone());
+    }
+
+    fn on_copy_start(&mut self, total_files: usize, total_bytes: u64) {
+        self.progress = Some(ProgressDisplay::new("Copying", total_files, total_bytes));
+    }
+
+    fn on_copy_progress(&mut self, files_done: usize, bytes_done: u64) {
+        if let Some(progress) = &self.progress {
+            progress.render(files_done, bytes_done);
+        }
+    }
+
+    fn on_file_copied(&mut self, path: &str, files_done: usize, bytes_done: u64) {
+        if let Some(progress) = &self.progress {
+            progress.log(files_done, bytes_done, path);
+        }
+    }
+
+    fn on_copy_finish(&mut self, files_done: usize, bytes_done: u64) {
+        if let Some(progress) = self.progress.take() {
+            progress.finish(files_done, bytes_done);
+        }
+    }
+
+    fn on_watch_sync(&mut self, summary: &SyncSummary) {
+        print_success(format!("{} files copied", format!("{}", summary.get_copied()).blue()).as_str());
+        if summary.get_deleted() > 0 {
+            print_success(format!("{} files deleted", format!("{}", summary.get_deleted()).blue()).as_str());
+        }
+        if summary.get_hard_linked() > 0 {
+            print_success(format!("{} hard links created", format!("{}", summary.get_hard_linked()).blue()).as_str());
+        }
+        if summary.get_updated() > 0 {
+            print_success(format!("{} files with only their metadata updated", format!("{}", summary.get_updated()).blue()).as_str());
+        }
+        if summary.get_dirs_created() > 0 {
+            print_success(format!("{} directories created", format!("{}", summary.get_dirs_created()).blue()).as_str());
+        }
+        if summary.get_dirs_removed() > 0 {
+            print_success(format!("{} directories removed", format!("{}", summary.get_dirs_removed()).blue()).as_str());
+        }
+    }
+}
+
+impl SyncSummary {
+    pub fn new(dry_run: bool) -> Self {
+        SyncSummary {
+            dry_run,
+            ..SyncSummary::default()
+        }
+    }
+
+    pub(crate) fn add_entry(&mut self, status: EntryStatus) {
+        match status {
+            EntryStatus::Ignored => self.ignored += 1,
+            EntryStatus::Conflict => self.conflicts += 1,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn add_error(&mut self) {
+        self.errors += 1;
+    }
+
+    pub(crate) fn add_copied(&mut self, files: usize, bytes: u64) {
+        self.copied += files;
+        self.bytes += bytes;
+    }
+
+    pub(crate) fn add_hard_linked(&mut self, files: usize) {
+        self.hard_linked += files;
+    }
+
+    pub(crate) fn add_verified(&mut self, files: usize) {
+        self.verified += files;
+    }
+
+    pub(crate) fn add_deleted(&mut self, files: usize) {
+        self.deleted += files;
+    }
+
+    pub(crate) fn add_updated(&mut self, files: usize) {
+        self.updated += files;
+    }
+
+    pub(crate) fn add_dirs_created(&mut self, dirs: usize) {
+        self.dirs_created += dirs;
+    }
+
+    pub(crate) fn add_dirs_removed(&mut self, dirs: usize) {
+        self.dirs_removed += dirs;
+    }
+
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = duration.as_millis();
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn get_copied(&self) -> usize {
+        self.copied
+    }
+
+    pub fn get_hard_linked(&self) -> usize {
+        self.hard_linked
+    }
+
+    pub fn get_deleted(&self) -> usize {
+        self.deleted
+    }
+
+    pub fn get_updated(&self) -> usize {
+        self.updated
+    }
+
+    pub fn get_dirs_created(&self) -> usize {
+        self.dirs_created
+    }
+
+    pub fn get_dirs_removed(&self) -> usize {
+        self.dirs_removed
+    }
+
+    pub fn get_ignored(&self) -> usize {
+        self.ignored
+    }
+
+    pub fn get_conflicts(&self) -> usize {
+        self.conflicts
+    }
+
+    pub fn get_verified(&self) -> usize {
+        self.verified
+    }
+
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    // files that failed and were skipped, and errors that stopped the sync
+    pub fn get_errors(&self) -> usize {
+        self.errors
+    }
+
+    pub fn get_duration_ms(&self) -> u128 {
+        self.duration_ms
+    }
+}
+
+fn print_json_line(record: &Record) {
+    println!("{}", serde_json::to_string(record).unwrap());
+}
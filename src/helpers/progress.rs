@@ -0,0 +1,107 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use crossterm::{cursor, execute, terminal};
+
+use super::logger::is_machine_output;
+
+// live progress line for long running operations
+// when stdout is not a terminal it falls back to plain log lines, and it stays silent while stdout holds json
+pub struct ProgressDisplay {
+    label: String,
+    total_files: usize,
+    total_bytes: u64,
+    started: Instant,
+    tty: bool,
+    hidden: bool,
+}
+
+impl ProgressDisplay {
+    pub fn new(label: &str, total_files: usize, total_bytes: u64) -> Self {
+        ProgressDisplay {
+            label: label.to_string(),
+            total_files,
+            total_bytes,
+            started: Instant::now(),
+            tty: io::stdout().is_terminal(),
+            hidden: is_machine_output(),
+        }
+    }
+
+    // redraw the progress line, does nothing when not on a terminal
+    pub fn render(&self, files_done: usize, bytes_done: u64) {
+        if !self.tty || self.hidden {
+            return;
+        }
+        let mut stdout = io::stdout();
+        let _ = execute!(stdout, cursor::MoveToColumn(0), terminal::Clear(terminal::ClearType::CurrentLine));
+        let _ = write!(stdout, "{}", self.status_line(files_done, bytes_done).cyan());
+        let _ = stdout.flush();
+    }
+
+    // a plain line for each finished item, used instead of the live line when not on a terminal
+    pub fn log(&self, files_done: usize, bytes_done: u64, message: &str) {
+        if self.tty || self.hidden {
+            return;
+        }
+        println!("[{}/{}] {} ({})", files_done, self.total_files, message, format_bytes(bytes_done));
+    }
+
+    pub fn finish(&self, files_done: usize, bytes_done: u64) {
+        if self.hidden {
+            return;
+        }
+        if self.tty {
+            self.render(files_done, bytes_done);
+            println!();
+        } else {
+            println!("{}", self.status_line(files_done, bytes_done));
+        }
+    }
+
+    fn status_line(&self, files_done: usize, bytes_done: u64) -> String {
+        let elapsed = self.started.elapsed();
+        let throughput = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => bytes_done as f64 / secs,
+            _ => 0.0,
+        };
+        let eta = match throughput {
+            t if t > 0.0 => format_duration(Duration::from_secs_f64(self.total_bytes.saturating_sub(bytes_done) as f64 / t)),
+            _ => "--".to_string(),
+        };
+        format!(
+            "{}: {}/{} files, {}/{} ({}/s, ETA {})",
+            self.label,
+            files_done,
+            self.total_files,
+            format_bytes(bytes_done),
+            format_bytes(self.total_bytes),
+            format_bytes(throughput as u64),
+            eta
+        )
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
@@ -0,0 +1,15 @@
+// the sync engine behind hsync, for programs that want to run and watch syncs themselves
+pub mod controllers;
+pub mod helpers;
+
+pub use controllers::conflict::{ConflictPolicy, ConflictRecord, ConflictResolution};
+pub use controllers::dir_tracker::{DirTracker, IgnoredEntry};
+pub use controllers::file_tracker::FileTracker;
+pub use controllers::sync_observer::{MessageKind, SyncObserver};
+pub use controllers::sync_options::SyncOptions;
+pub use controllers::sync_plan::{OperationKind, SyncOperation, SyncPlan};
+pub use controllers::syncer::Syncer;
+pub use controllers::tracker_config::{LinkPolicy, PreserveMetadata, SyncMode, VerifyLevel};
+pub use controllers::version_store::RetentionPolicy;
+pub use helpers::error::SyncError;
+pub use helpers::output::{EntryStatus, PlanEntry, SyncSummary};
@@ -1,44 +1,193 @@
-mod helpers;
-mod controllers;
-use std::{fs, path::Path};
+use std::{path::{Path, PathBuf}, time::Duration};
 
 use colored::Colorize;
-use controllers::dir_tracker::DirTracker;
 use fli::{init_fli_from_toml, Fli};
-use helpers::logger::{print_error, print_info, print_success};
+use hard_sync_cli::controllers::conflict::ConflictPolicy;
+use hard_sync_cli::controllers::copy_engine::DEFAULT_COPY_WORKERS;
+use hard_sync_cli::controllers::dir_tracker::DirTracker;
+use hard_sync_cli::controllers::dir_watcher::DEFAULT_DEBOUNCE_MS;
+use hard_sync_cli::controllers::drive_finder::{find_drive, list_removable_drives, DiskProvider, DriveInfo, SystemDiskProvider};
+use hard_sync_cli::controllers::integrity::{check_integrity, IntegrityStatus};
+use hard_sync_cli::controllers::sync_options::SyncOptions;
+use hard_sync_cli::controllers::sync_plan::SyncPlan;
+use hard_sync_cli::controllers::syncer::Syncer;
+use hard_sync_cli::controllers::tracker_config::{LinkPolicy, PreserveMetadata, SyncMode, SyncProfile, TrackerConfig, VerifyLevel};
+use hard_sync_cli::controllers::version_store::{RetentionPolicy, VersionStore};
+use hard_sync_cli::helpers::error::SyncError;
+use hard_sync_cli::helpers::hooks::run_hook;
+use hard_sync_cli::helpers::logger::{print_error, print_info, print_success, print_warning};
+use hard_sync_cli::helpers::output::{OutputFormat, OutputWriter};
+use hard_sync_cli::helpers::progress::format_bytes;
 
 // hard sync cli a cli tool for syncing 2 directories similar to rsync but with a few more features
 fn main() {
-    println!("{}", "Hard Sync CLI".cyan());
+    // on stderr, so stdout only holds json when --output json is passed
+    eprintln!("{}", "Hard Sync CLI".cyan());
     let mut app = init_fli_from_toml!();
     let sync = app.command("sync", "Syncs 2 directories");
-    sync.option("-s --src, <>", "Source Directory", sync_callback);
-    sync.option("-d --dest, <>", "Destination Directory", sync_callback);
-    sync.option("-i --init", "Initialize the directory", sync_callback);
-    sync.option("-r --reverse", "Initialize the directory", sync_callback);
+    add_scan_options(sync, sync_callback);
     sync.option("-dr --dry-run", "Dry run", sync_callback);
-    sync.option("-e --exclude, <...>", "Exclude files", sync_callback);
+    sync.option("-c --conflict, <>", "Conflict policy: newest-wins, source-wins, dest-wins, keep-both or skip", sync_callback);
+    sync.option("-b --backup", "Keep overwritten and deleted files in .hard_sync_cli/versions", sync_callback);
+    sync.option("-kl --keep-last, <>", "Only keep the last N backup runs", sync_callback);
+    sync.option("-kd --keep-days, <>", "Only keep backups made in the last N days", sync_callback);
+    sync.option("-vf --verify", "Read every copied file back and compare its hash with the source", sync_callback);
+    sync.option("-re --resume", "Continue the sync that was interrupted, from the journal in the destination", sync_callback);
     sync.allow_duplicate_callback(false);
 
+    let plan = app.command("plan", "Write the operations a sync would run to a plan file for review");
+    add_scan_options(plan, plan_callback);
+    plan.option("-c --conflict, <>", "Look for conflicts and resolve them with this policy when the plan is applied", plan_callback);
+    plan.option("-f --file, <>", "Path of the plan file (defaults to hsync-plan.json)", plan_callback);
+    plan.allow_duplicate_callback(false);
+
+    let apply = app.command("apply", "Run a plan written by `hsync plan`: hsync apply <plan>");
+    apply.default(apply_callback);
+    apply.option("-dr --dry-run", "Check the plan against the directories without changing anything", apply_callback);
+    apply.option("-c --conflict, <>", "Conflict policy, overrides the one saved in the plan", apply_callback);
+    apply.option("-b --backup", "Keep overwritten and deleted files in .hard_sync_cli/versions", apply_callback);
+    apply.option("-j --jobs, <>", "Number of parallel copy workers", apply_callback);
+    apply.option("-vf --verify", "Read every copied file back and compare its hash with the source", apply_callback);
+    apply.option("-o --output, <>", "Output format: text, json or ndjson", apply_callback);
+    apply.allow_duplicate_callback(false);
+
+    let watch = app.command("watch", "Keep syncing the source into the destination as files change");
+    watch.option("-s --src, <>", "Source Directory", watch_callback);
+    watch.option("-d --dest, <>", "Destination Directory", watch_callback);
+    watch.option("-i --init", "Initialize the directory", watch_callback);
+    watch.option("-e --exclude, <...>", "Exclude files", watch_callback);
+    watch.option("-del --delete", "Delete files from the destination that are deleted from the source", watch_callback);
+    watch.option("-b --backup", "Keep overwritten and deleted files in .hard_sync_cli/versions", watch_callback);
+    watch.option("-j --jobs, <>", "Number of parallel copy workers", watch_callback);
+    watch.option("-vf --verify", "Read every copied file back and compare its hash with the source", watch_callback);
+    watch.option("-a --archive", "Keep modification times, permissions and, when running as root, the owner of copied files", watch_callback);
+    watch.option("-xa --xattrs", "Keep extended attributes of copied files", watch_callback);
+    watch.option("-l --links, <>", "What to do with symbolic links: copy, follow or skip (the default)", watch_callback);
+    watch.option("-db --debounce, <>", "Milliseconds without new events before a burst of changes is synced", watch_callback);
+    watch.allow_duplicate_callback(false);
+
+    let drives = app.command("drives", "List mounted removable drives");
+    drives.default(drives_callback);
+    drives.option("-a --all", "List every mounted drive, not only removable ones", drives_callback);
+
+    let versions = app.command("versions", "List, restore and prune backed up versions of files");
+    versions.option("-d --dir, <>", "Synced directory holding the versions", versions_callback);
+    versions.option("-p --path, <>", "Path of the file relative to the directory", versions_callback);
+    versions.option("-rs --restore, <>", "Timestamp of the version to restore", versions_callback);
+    versions.option("-kl --keep-last, <>", "Remove all but the last N backup runs", versions_callback);
+    versions.option("-kd --keep-days, <>", "Remove backups older than N days", versions_callback);
+    versions.allow_duplicate_callback(false);
+
+    // scrub is the name other backup tools use for the same check
+    for name in ["verify", "scrub"] {
+        let verify = app.command(name, "Rehash a synced directory and report files that no longer match tracker.json: hsync verify <dir>");
+        verify.default(verify_callback);
+        verify.option("-j --jobs, <>", "Number of threads used to hash files", verify_callback);
+    }
+
+    let profiles = app.command("profiles", "List the sync profiles found in the config files");
+    profiles.default(profiles_callback);
+    profiles.option("-s --src, <>", "Also read the project config of this source directory", profiles_callback);
+    profiles.option("-d --dest, <>", "Also read the project config of this destination directory", profiles_callback);
+    profiles.allow_duplicate_callback(false);
+
     app.run();
 }
 
+// the options deciding what gets synced, shared by `sync` and `plan`
+fn add_scan_options(command: &mut Fli, callback: fn(&Fli)) {
+    command.option("-s --src, <>", "Source Directory", callback);
+    command.option("-d --dest, <>", "Destination Directory", callback);
+    command.option("-dl --dest-label, <>", "Label or UUID of the drive holding the destination, --dest is then relative to the drive", callback);
+    command.option("-i --init", "Initialize the directory", callback);
+    command.option("-r --reverse", "Swap the source and destination directories", callback);
+    command.option("-e --exclude, <...>", "Exclude files", callback);
+    command.option("-del --delete", "Delete files from the destination that no longer exist in the source", callback);
+    command.option("-tw --two-way", "Sync changes in both directions", callback);
+    command.option("-cs --checksum", "Rehash every file instead of reusing hashes saved in tracker.json", callback);
+    command.option("-a --archive", "Keep modification times, permissions and, when running as root, the owner of copied files", callback);
+    command.option("-xa --xattrs", "Keep extended attributes of copied files", callback);
+    command.option("-l --links, <>", "What to do with symbolic links: copy, follow or skip (the default)", callback);
+    command.option("-j --jobs, <>", "Number of threads used to scan, hash and copy files", callback);
+    command.option("-o --output, <>", "Output format: text, json or ndjson", callback);
+    command.option("-p --profile, <>", "Name of a profile from the config file, flags passed on the command line override it", callback);
+}
+
 fn sync_callback(x: &Fli) {
-    // get the src and dest path
-    let src = match x.get_values("src".to_string()) {
-        Ok(v) => v.first().unwrap().clone(),
-        Err(e) => {
-            print_error(&e);
-            return;
-        }
+    run_with_output(x, x.is_passed("-dr".to_owned()), |x, output| run_sync(x, output, None));
+}
+
+fn plan_callback(x: &Fli) {
+    let file = x.get_values("file".to_owned())
+        .map(|v| v.first().unwrap().clone())
+        .unwrap_or("hsync-plan.json".to_string());
+    run_with_output(x, true, |x, output| run_sync(x, output, Some(Path::new(&file))));
+}
+
+fn apply_callback(x: &Fli) {
+    run_with_output(x, x.is_passed("-dr".to_owned()), run_apply);
+}
+
+// the run stopped before it could sync, or could not be started
+const EXIT_FAILURE: i32 = 1;
+// the run finished but some files failed and were skipped
+const EXIT_PARTIAL: i32 = 2;
+
+// commands that print as they go only have to report the error that stopped them
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        print_error(&e);
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+// errors that stop the run are returned by `run`, failures on single files are reported through the writer and the run goes on
+fn run_with_output(x: &Fli, dry_run: bool, run: impl FnOnce(&Fli, &mut OutputWriter) -> Result<(), String>) {
+    let format = match x.get_values("output".to_owned()) {
+        Ok(v) => match OutputFormat::from_name(v.first().unwrap()) {
+            Ok(format) => format,
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Err(_) => OutputFormat::Text,
+    };
+    let mut output = OutputWriter::new(format, dry_run);
+    let result = run(x, &mut output);
+    if let Err(e) = &result {
+        output.error(None, e);
+    }
+    output.finish();
+    // scripts and schedulers only see the exit code, so it tells a stopped run from one that skipped some files
+    if result.is_err() {
+        std::process::exit(EXIT_FAILURE);
+    }
+    if output.has_errors() {
+        std::process::exit(EXIT_PARTIAL);
+    }
+}
+
+// scan both directories and plan the sync, then run the plan or, with `plan_file`, save it for `hsync apply`
+fn run_sync(x: &Fli, output: &mut OutputWriter, plan_file: Option<&Path>) -> Result<(), String> {
+    // settings missing from the command line are taken from the profile
+    let profile = match x.get_values("profile".to_owned()) {
+        Ok(v) => load_profile(x, v.first().unwrap())?,
+        Err(_) => SyncProfile::default(),
     };
 
-    let dest = match x.get_values("dest".to_string()) {
-        Ok(v) => v.first().unwrap().clone(),
-        Err(e) => {
-            print_error(format!("Destination directory not provided: {}", e).as_str());
-            return;
+    // get the src and dest path
+    let src = get_option(x, "src", profile.get_src()).ok_or("Source directory not provided")?;
+
+    let dest = match get_option(x, "dest-label", profile.get_dest_label()) {
+        // the destination is on a drive found by label, wherever it is mounted
+        Some(label) => {
+            let drive = find_drive(&SystemDiskProvider, &label)?;
+            print_info(format!("Using drive {} mounted at {:?}", label, drive.get_mount_point()).as_str());
+            let relative = get_option(x, "dest", profile.get_dest()).unwrap_or_default();
+            drive.get_mount_point().join(relative.trim_start_matches('/')).to_string_lossy().to_string()
         }
+        None => get_option(x, "dest", profile.get_dest()).ok_or("Destination directory not provided")?,
     };
 
     // get path of src and dest as Path and check if reverse is passed
@@ -46,93 +195,471 @@ fn sync_callback(x: &Fli) {
                 true => (Path::new(&src), Path::new(&dest)),
                 false => (Path::new(&dest), Path::new(&src))
     };
-    
-    // check if src and dest exists
-    if !src.exists() {
-        print_error(format!("Source directory {:?} does not exist", src).as_str());
-        return;
+
+    // planning never touches the directories, so it behaves like a dry run
+    let dry_run = x.is_passed("-dr".to_owned()) || plan_file.is_some();
+    let resume = x.is_passed("-re".to_owned());
+    if resume && dry_run {
+        return Err("--resume can not be combined with --dry-run".to_string());
+    }
+    // pre sync hooks run first, they may mount the drives the directories live on
+    if !dry_run {
+        run_hooks(profile.get_hooks().get_pre_sync(), src, dest)?;
     }
 
-    if !dest.exists() {
-        print_error("Destination directory does not exist");
-        return;
+    let conflict_policy = match get_option(x, "conflict", profile.get_conflict()) {
+        Some(v) => Some(ConflictPolicy::from_name(&v)?),
+        None => None,
+    };
+
+    // cap the worker pool used for scanning and hashing, and the number of copy workers
+    let profile_jobs = profile.get_jobs().map(|jobs| jobs.to_string());
+    let copy_workers = setup_jobs(get_option(x, "jobs", profile_jobs.as_ref()), true)?;
+
+    // in mirror mode also remove what was deleted from src
+    let mode = match (x.is_passed("-tw".to_owned()), x.is_passed("-del".to_owned())) {
+        (true, _) => SyncMode::TwoWay,
+        (false, true) if profile.get_mode() != SyncMode::TwoWay => SyncMode::Mirror,
+        _ => profile.get_mode(),
+    };
+    let mut exclude = x.get_values("exclude".to_owned()).unwrap_or_default();
+    exclude.extend(profile.get_exclude().iter().cloned());
+
+    let options = SyncOptions::new(src, dest)
+        .with_mode(mode)
+        .with_init(x.is_passed("-i".to_owned()))
+        .with_dry_run(dry_run)
+        .with_checksum(x.is_passed("-cs".to_owned()))
+        .with_exclude(exclude)
+        .with_conflict(conflict_policy)
+        .with_backup(x.is_passed("-b".to_owned()) || profile.get_backup())
+        .with_retention(get_retention_policy(x)?)
+        .with_verify(get_verify_level(x, profile.get_verify()))
+        .with_preserve(get_preserve(x, &profile))
+        .with_links(get_link_policy(x, profile.get_links())?)
+        .with_copy_workers(copy_workers);
+    let syncer = Syncer::new(options);
+
+    if let Some(plan_file) = plan_file {
+        // list the plan the way a dry run does, conflicts are resolved when it is applied
+        let plan = syncer.plan(output).map_err(cli_error)?;
+        let summary = syncer.preview(&plan, output);
+        output.set_summary(summary);
+        plan.save(plan_file)?;
+        print_success(format!("Plan with {} operations written to {:?}", plan.get_operations().len(), plan_file).as_str());
+        return Ok(());
     }
-    
 
-    // check if path are same 
-    if src == dest {
-        print_error("Source and destination directories are same");
-        return;
+    // an interrupted sync runs the rest of its journaled plan instead of scanning again
+    let summary = match resume {
+        true => syncer.resume(output).map_err(cli_error)?,
+        false => syncer.run(output).map_err(cli_error)?,
+    };
+    output.set_summary(summary);
+    if output.is_text() {
+        print_summary(output, resume || syncer.get_options().is_deleting());
+    }
+    if !dry_run {
+        if let Err(e) = run_hooks(profile.get_hooks().get_post_sync(), src, dest) {
+            output.error(None, &e);
+        }
+    }
+    Ok(())
+}
+
+// the library says what went wrong, the cli adds the flag that fixes it
+fn cli_error(e: SyncError) -> String {
+    match e {
+        SyncError::NotInitialized { .. } => format!("{}, pass -i to initialize it", e),
+        e => e.to_string(),
+    }
+}
+
+// run a plan saved by `hsync plan`, refusing every operation whose files changed since it was made
+fn run_apply(x: &Fli, output: &mut OutputWriter) -> Result<(), String> {
+    let plan_file = x.get_arg_at(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .ok_or("Pass the plan file to apply: hsync apply <plan>")?;
+    let plan = SyncPlan::load(Path::new(&plan_file))?;
+
+    let copy_workers = setup_jobs(x.get_values("jobs".to_owned()).ok().and_then(|v| v.first().cloned()), false)?;
+    let conflict_policy = match x.get_values("conflict".to_owned()) {
+        Ok(v) => Some(ConflictPolicy::from_name(v.first().unwrap())?),
+        Err(_) => None,
+    };
+    let options = SyncOptions::new(Path::new(plan.get_src()), Path::new(plan.get_dest()))
+        .with_dry_run(x.is_passed("-dr".to_owned()))
+        .with_conflict(conflict_policy)
+        .with_backup(x.is_passed("-b".to_owned()))
+        .with_verify(get_verify_level(x, VerifyLevel::None))
+        .with_copy_workers(copy_workers);
+    let summary = Syncer::new(options).apply(&plan, output).map_err(cli_error)?;
+    output.set_summary(summary);
+    if output.is_text() {
+        print_summary(output, true);
+    }
+    Ok(())
+}
+
+fn print_summary(output: &OutputWriter, delete: bool) {
+    let summary = output.get_summary();
+    println!();
+    print_success(format!("{} files copied", format!("{}", summary.get_copied()).blue()).as_str());
+    if summary.get_hard_linked() > 0 {
+        print_success(format!("{} hard links created", format!("{}", summary.get_hard_linked()).blue()).as_str());
+    }
+    if delete {
+        print_success(format!("{} files deleted", format!("{}", summary.get_deleted()).blue()).as_str());
+    }
+    if summary.get_updated() > 0 {
+        print_success(format!("{} files with only their metadata updated", format!("{}", summary.get_updated()).blue()).as_str());
+    }
+    if summary.get_dirs_created() > 0 {
+        print_success(format!("{} directories created", format!("{}", summary.get_dirs_created()).blue()).as_str());
+    }
+    if summary.get_dirs_removed() > 0 {
+        print_success(format!("{} directories removed", format!("{}", summary.get_dirs_removed()).blue()).as_str());
+    }
+    print_success(format!("{} files ignored", format!("{}", summary.get_ignored()).red()).as_str());
+    if summary.get_verified() > 0 {
+        print_success(format!("{} files verified", format!("{}", summary.get_verified()).blue()).as_str());
+    }
+    print_conflict_report(output);
+}
+
+// the number of copy workers, with `global_pool` the rayon pool used for scanning and hashing is capped too
+fn setup_jobs(jobs: Option<String>, global_pool: bool) -> Result<usize, String> {
+    let Some(jobs) = jobs else {
+        return Ok(DEFAULT_COPY_WORKERS);
+    };
+    let jobs = match jobs.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => jobs,
+        _ => return Err("--jobs expects a number greater than 0".to_string()),
+    };
+    if global_pool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .map_err(|e| format!("Could not start {} worker threads: {}", jobs, e))?;
     }
-    
+    Ok(jobs)
+}
+
+fn watch_callback(x: &Fli) {
+    exit_on_error(run_watch(x));
+}
 
-    let mut src_dir = DirTracker::new(src).unwrap();
-    let mut dest_dir = DirTracker::new(dest).unwrap();
+// sync once, then copy every file that changes in the source until the process is stopped
+fn run_watch(x: &Fli) -> Result<(), String> {
+    let (src, dest) = match (x.get_values("src".to_owned()), x.get_values("dest".to_owned())) {
+        (Ok(src), Ok(dest)) => (src.first().unwrap().clone(), dest.first().unwrap().clone()),
+        _ => return Err("Both --src and --dest are required".to_string()),
+    };
 
-    if dest_dir.dir_initialized().is_err() {
-        if !x.is_passed("-i".to_owned()) {
-            print_error("Dest directory is not initialized pass -i to initailise it.");
-            return;
+    let mut copy_workers = DEFAULT_COPY_WORKERS;
+    if let Ok(v) = x.get_values("jobs".to_owned()) {
+        match v.first().unwrap().parse::<usize>() {
+            Ok(jobs) if jobs > 0 => copy_workers = jobs,
+            _ => return Err("--jobs expects a number greater than 0".to_string()),
         }
-        match dest_dir.setup_dir_config() {
-            Ok(_) => {
-                print_success("Source directory initialized");
-            }
-            Err(e) => {
-                print_error(&e);
-                return;
-            }
+    }
+    let mut debounce = DEFAULT_DEBOUNCE_MS;
+    if let Ok(v) = x.get_values("debounce".to_owned()) {
+        match v.first().unwrap().parse::<u64>() {
+            Ok(ms) => debounce = ms,
+            Err(_) => return Err("--debounce expects a number of milliseconds".to_string()),
         }
     }
+    let mode = match x.is_passed("-del".to_owned()) {
+        true => SyncMode::Mirror,
+        false => SyncMode::OneWay,
+    };
+    let links = get_link_policy(x, LinkPolicy::default())?;
+    let options = SyncOptions::new(Path::new(&src), Path::new(&dest))
+        .with_mode(mode)
+        .with_init(x.is_passed("-i".to_owned()))
+        .with_exclude(x.get_values("exclude".to_owned()).unwrap_or_default())
+        .with_backup(x.is_passed("-b".to_owned()))
+        .with_verify(get_verify_level(x, VerifyLevel::None))
+        .with_preserve(get_preserve(x, &SyncProfile::default()))
+        .with_links(links)
+        .with_copy_workers(copy_workers);
+
+    print_info("Press Ctrl+C to stop watching");
+    let mut output = OutputWriter::new(OutputFormat::Text, false);
+    Syncer::new(options).watch(Duration::from_millis(debounce), &mut output).map_err(cli_error)
+}
 
-    src_dir.import_files_from_directory(true);
-    dest_dir.import_files_from_directory(true);
+// --verify checks hashes, without it the profile decides
+fn get_verify_level(x: &Fli, fallback: VerifyLevel) -> VerifyLevel {
+    match x.is_passed("-vf".to_owned()) {
+        true => VerifyLevel::Hash,
+        false => fallback,
+    }
+}
 
-    if x.is_passed("-e".to_owned()) {
-        let exclude = x.get_values("exclude".to_owned()).unwrap();
-        for file in exclude {
-            dest_dir.add_ignore(file);
+// --archive keeps the times, permissions and owner, --xattrs the extended attributes, the profile can turn on either
+fn get_preserve(x: &Fli, profile: &SyncProfile) -> PreserveMetadata {
+    let preserve = match x.is_passed("-a".to_owned()) || profile.get_archive() {
+        true => PreserveMetadata::archive(),
+        false => PreserveMetadata::default(),
+    };
+    preserve.with_xattrs(x.is_passed("-xa".to_owned()) || profile.get_xattrs())
+}
+
+// --links wins over the profile
+fn get_link_policy(x: &Fli, fallback: LinkPolicy) -> Result<LinkPolicy, String> {
+    match x.get_values("links".to_owned()) {
+        Ok(v) => LinkPolicy::from_name(v.first().unwrap()),
+        Err(_) => Ok(fallback),
+    }
+}
+
+// report the files of a synced directory that went missing, appeared or rotted since the last sync
+// exits with 1 when any are found, so scheduled checks can alert on it
+fn verify_callback(x: &Fli) {
+    let Some(dir) = x.get_arg_at(1).filter(|arg| !arg.starts_with('-')) else {
+        print_error("Pass the directory to verify: hsync verify <dir>");
+        std::process::exit(EXIT_FAILURE);
+    };
+    let report = setup_jobs(x.get_values("jobs".to_owned()).ok().and_then(|v| v.first().cloned()), true)
+        .and_then(|_| check_integrity(Path::new(&dir)));
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            print_error(&e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    for issue in report.get_issues() {
+        let status = match issue.get_status() {
+            IntegrityStatus::Missing => issue.get_status().to_string().red(),
+            IntegrityStatus::New => issue.get_status().to_string().green(),
+            IntegrityStatus::Modified => issue.get_status().to_string().yellow(),
+            IntegrityStatus::Bitrot => issue.get_status().to_string().magenta(),
+        };
+        // a short prefix of each hash is enough to tell them apart
+        match (issue.get_expected_hash(), issue.get_actual_hash()) {
+            (Some(expected), Some(actual)) => println!("{} ({}) expected {}, found {}", issue.get_path(), status.underline(), short_hash(expected), short_hash(actual)),
+            _ => println!("{} ({})", issue.get_path(), status.underline()),
+        }
+    }
+    for e in report.get_errors() {
+        print_error(format!("Could not check {}", e).as_str());
+    }
+    println!();
+    print_info(format!("{} files checked", report.get_checked()).as_str());
+    let counts = [
+        (IntegrityStatus::Missing, "missing"),
+        (IntegrityStatus::New, "not synced yet"),
+        (IntegrityStatus::Modified, "edited since the last sync"),
+        (IntegrityStatus::Bitrot, "changed without a new modification time (bitrot)"),
+    ];
+    for (status, description) in counts {
+        let count = report.count(status);
+        if count > 0 {
+            print_warning(format!("{} files {}", count, description).as_str());
         }
     }
+    if report.has_failures() {
+        print_error("The directory does not match its last synced state");
+        std::process::exit(EXIT_FAILURE);
+    }
+    print_success("Every tracked file matches its last synced state");
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+// the value passed on the command line, or the profile's when the flag is missing
+fn get_option(x: &Fli, name: &str, fallback: Option<&String>) -> Option<String> {
+    match x.get_values(name.to_owned()) {
+        Ok(v) => v.first().cloned(),
+        Err(_) => fallback.cloned(),
+    }
+}
 
-    // get the diff (files that are in src but not in dest)
-    let diff = src_dir.get_dir_diff(&dest_dir);
-    if diff.len() == 0 {
-        print_success("No diff found");
+// the profile from the user config and the project configs of the directories passed on the command line
+// a profile found there may name directories with a project config of their own, which then overrides it
+fn load_profile(x: &Fli, name: &str) -> Result<SyncProfile, String> {
+    let mut roots = config_roots(x, None);
+    let mut config = TrackerConfig::load(&roots)?;
+    if let Some(profile) = config.get_profile(name) {
+        let profile_roots = config_roots(x, Some(profile));
+        if profile_roots != roots {
+            roots = profile_roots;
+            config = TrackerConfig::load(&roots)?;
+        }
     }
-    
-    // warn about dry running turned on
-    if x.is_passed("-dr".to_owned()) {
-        print_info("Dry run turned on");
+    match config.get_profile(name) {
+        Some(profile) => Ok(profile.clone()),
+        None => Err(format!(
+            "Profile {} not found, looked in {:?}",
+            name,
+            TrackerConfig::get_config_paths(&roots)
+        )),
     }
-    let mut ignore_count = 0;
-    for file in &diff {
-        let status = match dest_dir.get_file(file.get_relative_path(Path::new(&src_dir.get_path()))) {
-            Some(_) => "Modified".yellow(),
-            None => "New".green(),
-        };
+}
+
+// the source and then the destination, whose .hard_sync_cli may hold a project config
+// a destination relative to a drive found by label is only known once the drive is found, so it is left out
+fn config_roots(x: &Fli, profile: Option<&SyncProfile>) -> Vec<PathBuf> {
+    let mut roots = vec![get_option(x, "src", profile.and_then(|p| p.get_src()))];
+    if get_option(x, "dest-label", profile.and_then(|p| p.get_dest_label())).is_none() {
+        roots.push(get_option(x, "dest", profile.and_then(|p| p.get_dest())));
+    }
+    roots.into_iter().flatten().map(PathBuf::from).collect()
+}
+
+// run the hooks in order, stopping at the first one that fails
+fn run_hooks(hooks: &[String], src: &Path, dest: &Path) -> Result<(), String> {
+    for hook in hooks {
+        print_info(format!("Running hook: {}", hook).as_str());
+        run_hook(hook, src, dest)?;
+    }
+    Ok(())
+}
+
+fn profiles_callback(x: &Fli) {
+    exit_on_error(run_profiles(x));
+}
+
+fn run_profiles(x: &Fli) -> Result<(), String> {
+    let roots = config_roots(x, None);
+    let config = TrackerConfig::load(&roots)?;
+    if config.get_profiles().is_empty() {
+        print_info(format!("No profiles found, looked in {:?}", TrackerConfig::get_config_paths(&roots)).as_str());
+        return Ok(());
+    }
+    let mut names: Vec<&String> = config.get_profiles().keys().collect();
+    names.sort();
+    for name in names {
+        let profile = config.get_profile(name).unwrap();
         println!(
-            "{} ({})",
-            file.get_relative_path(Path::new(&src_dir.get_path())),
-            status.underline()
+            "{} {} -> {} ({:?})",
+            name.cyan(),
+            profile.get_src().map(|s| s.as_str()).unwrap_or("-"),
+            profile.get_dest().or(profile.get_dest_label()).map(|s| s.as_str()).unwrap_or("-"),
+            profile.get_mode()
         );
-        // copy the file
-        let dest_file = dest.join(file.get_relative_path(Path::new(&src_dir.get_path())));
-        if dest_dir.load_ignore().is_ok() && dest_dir.is_ignored( &file.get_relative_path(Path::new(&src_dir.get_path()))) {
-            ignore_count+=1;
-            continue;
-        }
-        // to implement dry run
-        if !x.is_passed("-dr".to_owned()) {
-            if !dest_file.parent().unwrap().exists() {
-                fs::create_dir_all(dest_file.parent().unwrap()).unwrap();
-            }
-            fs::copy(file.get_path(), dest_file.clone()).unwrap();
-        }
     }
-    println!("");
-    print_success(format!("{} files copied", format!("{}", diff.len() - ignore_count).blue()).as_str());
-    print_success(format!("{} files ignored", format!("{}", ignore_count).red()).as_str());
-    print_info(format!("All ignored files patterns: {:?}", dest_dir.get_ignore()).as_str());
+    Ok(())
+}
+
+fn get_retention_policy(x: &Fli) -> Result<Option<RetentionPolicy>, String> {
+    if let Ok(v) = x.get_values("keep-last".to_owned()) {
+        return match v.first().unwrap().parse::<usize>() {
+            Ok(count) => Ok(Some(RetentionPolicy::KeepLast(count))),
+            Err(_) => Err("--keep-last expects a number".to_string()),
+        };
+    }
+    if let Ok(v) = x.get_values("keep-days".to_owned()) {
+        return match v.first().unwrap().parse::<i64>() {
+            Ok(days) if days >= 0 => Ok(Some(RetentionPolicy::KeepDays(days))),
+            _ => Err("--keep-days expects a number".to_string()),
+        };
+    }
+    Ok(None)
+}
+
+fn drives_callback(x: &Fli) {
+    let provider = SystemDiskProvider;
+    let drives = match x.is_passed("-a".to_owned()) {
+        true => provider.list_disks(),
+        false => list_removable_drives(&provider),
+    };
+    if drives.is_empty() {
+        print_info("No removable drives found");
+        return;
+    }
+    for drive in &drives {
+        print_drive(drive);
+    }
+}
+
+fn print_drive(drive: &DriveInfo) {
+    println!(
+        "{} {} ({}, {} free of {})",
+        drive.get_label().unwrap_or(drive.get_name()).blue(),
+        drive.get_mount_point().display(),
+        drive.get_file_system(),
+        format_bytes(drive.get_available_space()),
+        format_bytes(drive.get_total_space())
+    );
+    if let Some(uuid) = drive.get_uuid() {
+        println!("  uuid: {}", uuid);
+    }
+}
+
+fn versions_callback(x: &Fli) {
+    exit_on_error(run_versions(x));
+}
+
+// list, restore and prune the versions kept by `sync --backup`
+fn run_versions(x: &Fli) -> Result<(), String> {
+    let dir = match x.get_values("dir".to_owned()) {
+        Ok(v) => v.first().unwrap().clone(),
+        Err(e) => return Err(format!("Directory not provided: {}", e)),
+    };
+    let dir = Path::new(&dir);
+    if DirTracker::new(dir).and_then(|d| d.dir_initialized()).is_err() {
+        return Err(format!("{:?} is not an initialized directory", dir));
+    }
+    let versions = VersionStore::new(dir);
+
+    if let Some(retention) = get_retention_policy(x)? {
+        let removed = versions.prune(retention).map_err(|e| format!("Failed to prune backups: {}", e))?;
+        print_success(format!("Removed {} old backup runs", removed).as_str());
+    }
+
+    let path = match x.get_values("path".to_owned()) {
+        Ok(v) => v.first().unwrap().clone(),
+        Err(_) => return Ok(()),
+    };
+
+    if let Ok(v) = x.get_values("restore".to_owned()) {
+        let timestamp = v.first().unwrap();
+        versions.restore(&path, timestamp)?;
+        print_success(format!("Restored {} from {}", path, timestamp).as_str());
+        return Ok(());
+    }
+
+    let list = versions.list_versions(&path).map_err(|e| e.to_string())?;
+    if list.is_empty() {
+        print_info(format!("No versions stored for {}", path).as_str());
+        return Ok(());
+    }
+    for version in list {
+        println!("{} ({}) {}", version.get_timestamp().blue(), format_bytes(version.get_size()), version.get_path().display().to_string().dimmed());
+    }
+    print_info("Pass --restore <timestamp> to restore a version");
+    Ok(())
+}
 
+// list every conflicting file and what the policy did with it
+fn print_conflict_report(output: &OutputWriter) {
+    let (report, Some(policy)) = (output.get_conflicts(), output.get_conflict_policy()) else {
+        return;
+    };
+    println!();
+    print_warning(format!("{} files changed on both sides (policy: {})", format!("{}", report.len()).red(), policy).as_str());
+    for record in report {
+        match record.get_resolution() {
+            Ok(resolution) => println!(
+                "  {} (src: {}, dest: {}) {}",
+                record.get_relative_path(),
+                record.get_src_change(),
+                record.get_dest_change(),
+                resolution.to_string().cyan()
+            ),
+            Err(e) => println!(
+                "  {} (src: {}, dest: {}) {}",
+                record.get_relative_path(),
+                record.get_src_change(),
+                record.get_dest_change(),
+                format!("failed: {}", e).red()
+            ),
+        }
+    }
 }
@@ -0,0 +1,396 @@
+// end to end syncs between two temporary directories, through the library api
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use hard_sync_cli::controllers::sync_journal::SyncJournal;
+use hard_sync_cli::controllers::version_store::VersionStore;
+use hard_sync_cli::{ConflictPolicy, LinkPolicy, OperationKind, PreserveMetadata, SyncError, SyncMode, SyncObserver, SyncOptions, Syncer};
+
+fn write(dir: &Path, path: &str, content: &str) {
+    let path = dir.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn read(dir: &Path, path: &str) -> String {
+    fs::read_to_string(dir.join(path)).unwrap()
+}
+
+// the names directly in `dir`, leaving out the sync state
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name != ".hard_sync_cli")
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn mirror_deletes_what_the_source_removed() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "keep.txt", "keep");
+    write(src.path(), "old/gone.txt", "gone");
+    write(src.path(), "drop.txt", "drop");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_mode(SyncMode::Mirror).with_init(true);
+    let summary = Syncer::new(options.clone()).run(&mut ()).unwrap();
+    assert_eq!(summary.get_copied(), 3);
+    assert_eq!(names(dest.path()), ["drop.txt", "keep.txt", "old"]);
+
+    fs::remove_file(src.path().join("drop.txt")).unwrap();
+    fs::remove_dir_all(src.path().join("old")).unwrap();
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_deleted(), 2);
+    assert_eq!(summary.get_dirs_removed(), 1);
+    assert_eq!(summary.get_errors(), 0);
+    assert_eq!(names(dest.path()), ["keep.txt"]);
+    assert_eq!(read(dest.path(), "keep.txt"), "keep");
+}
+
+#[test]
+fn one_way_sync_keeps_what_the_source_removed() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "a.txt", "a");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    fs::remove_file(src.path().join("a.txt")).unwrap();
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_deleted(), 0);
+    assert_eq!(read(dest.path(), "a.txt"), "a");
+}
+
+#[test]
+fn keep_both_saves_the_destination_version_next_to_the_source_one() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "notes.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_mode(SyncMode::TwoWay).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    assert_eq!(read(dest.path(), "notes.txt"), "first");
+
+    // both sides change the file before the next sync
+    write(src.path(), "notes.txt", "from the source");
+    write(dest.path(), "notes.txt", "from the destination");
+    let summary = Syncer::new(options.with_conflict(Some(ConflictPolicy::KeepBoth))).run(&mut ()).unwrap();
+    assert_eq!(summary.get_conflicts(), 1);
+    assert_eq!(summary.get_errors(), 0);
+
+    for dir in [src.path(), dest.path()] {
+        let names = names(dir);
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert_eq!(read(dir, "notes.txt"), "from the source");
+        let renamed = names.iter().find(|name| name.starts_with("notes.txt.conflict-")).unwrap();
+        assert_eq!(read(dir, renamed), "from the destination");
+    }
+}
+
+#[test]
+fn two_way_sync_skips_conflicts_without_a_policy() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "notes.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_mode(SyncMode::TwoWay).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    write(src.path(), "notes.txt", "from the source");
+    write(dest.path(), "notes.txt", "from the destination");
+    Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(read(src.path(), "notes.txt"), "from the source");
+    assert_eq!(read(dest.path(), "notes.txt"), "from the destination");
+}
+
+#[test]
+fn leftover_temp_files_are_only_removed_from_the_destination() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "sub/a.txt", "a");
+    write(src.path(), ".mine.hsync-tmp", "not ours");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    write(dest.path(), "sub/.a.txt.hsync-tmp", "half a copy");
+
+    Syncer::new(options.clone().with_dry_run(true)).run(&mut ()).unwrap();
+    assert!(dest.path().join("sub/.a.txt.hsync-tmp").exists());
+    Syncer::new(options).run(&mut ()).unwrap();
+    assert!(!dest.path().join("sub/.a.txt.hsync-tmp").exists());
+    assert!(!dest.path().join(".mine.hsync-tmp").exists());
+    assert_eq!(read(src.path(), ".mine.hsync-tmp"), "not ours");
+}
+
+#[test]
+fn a_sync_that_can_not_start_returns_why() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let missing = dest.path().join("missing");
+
+    let error = Syncer::new(SyncOptions::new(src.path(), dest.path())).run(&mut ()).unwrap_err();
+    assert!(matches!(error, SyncError::NotInitialized { .. }), "{:?}", error);
+    let error = Syncer::new(SyncOptions::new(src.path(), &missing)).run(&mut ()).unwrap_err();
+    assert_eq!(error, SyncError::NotFound { path: missing.to_string_lossy().to_string() });
+    let error = Syncer::new(SyncOptions::new(src.path(), dest.path()).with_dry_run(true)).resume(&mut ()).unwrap_err();
+    assert!(matches!(error, SyncError::InvalidOptions { .. }), "{:?}", error);
+}
+
+#[test]
+fn one_way_conflicts_only_write_the_destination() {
+    for policy in [ConflictPolicy::DestWins, ConflictPolicy::NewestWins, ConflictPolicy::KeepBoth] {
+        let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(src.path(), "notes.txt", "first");
+
+        let options = SyncOptions::new(src.path(), dest.path()).with_init(true).with_conflict(Some(policy));
+        Syncer::new(options.clone()).run(&mut ()).unwrap();
+        write(src.path(), "notes.txt", "from the source");
+        write(dest.path(), "notes.txt", "from the destination");
+        // the destination edit is the newest one
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options().write(true).open(dest.path().join("notes.txt")).unwrap().set_modified(later).unwrap();
+        let summary = Syncer::new(options).run(&mut ()).unwrap();
+        assert_eq!(summary.get_conflicts(), 1, "{}", policy);
+        assert_eq!(summary.get_errors(), 0, "{}", policy);
+
+        assert_eq!(names(src.path()), ["notes.txt"], "{}", policy);
+        assert_eq!(read(src.path(), "notes.txt"), "from the source", "{}", policy);
+        let names = names(dest.path());
+        match policy {
+            ConflictPolicy::KeepBoth => {
+                assert_eq!(names.len(), 2, "{:?}", names);
+                assert_eq!(read(dest.path(), "notes.txt"), "from the source");
+                let renamed = names.iter().find(|name| name.starts_with("notes.txt.conflict-")).unwrap();
+                assert_eq!(read(dest.path(), renamed), "from the destination");
+            }
+            _ => {
+                assert_eq!(names, ["notes.txt"], "{}", policy);
+                assert_eq!(read(dest.path(), "notes.txt"), "from the destination", "{}", policy);
+            }
+        }
+    }
+}
+
+#[test]
+fn one_way_sync_without_a_source_baseline_has_no_conflicts() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "notes.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true).with_conflict(Some(ConflictPolicy::DestWins));
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    // the source hashes saved on the first sync are its baseline
+    fs::remove_dir_all(dest.path().join(".hard_sync_cli").join("sources")).unwrap();
+    write(src.path(), "notes.txt", "from the source");
+    write(dest.path(), "notes.txt", "from the destination");
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_conflicts(), 0);
+    assert_eq!(read(dest.path(), "notes.txt"), "from the source");
+}
+
+// stops a watch right after its first sync
+struct SyncOnce;
+
+impl SyncObserver for SyncOnce {
+    fn keep_watching(&mut self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn watch_mirror_backs_up_what_it_deletes_and_overwrites() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "edited.txt", "old");
+    write(src.path(), "removed.txt", "removed");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_mode(SyncMode::Mirror).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    write(src.path(), "edited.txt", "new");
+    fs::remove_file(src.path().join("removed.txt")).unwrap();
+    Syncer::new(options.with_backup(true)).watch(Duration::from_millis(10), &mut SyncOnce).unwrap();
+
+    assert_eq!(names(dest.path()), ["edited.txt"]);
+    assert_eq!(read(dest.path(), "edited.txt"), "new");
+    let versions = VersionStore::new(dest.path());
+    for (path, content) in [("edited.txt", "old"), ("removed.txt", "removed")] {
+        let list = versions.list_versions(path).unwrap();
+        assert_eq!(list.len(), 1, "{}", path);
+        assert_eq!(fs::read_to_string(list[0].get_path()).unwrap(), content);
+    }
+}
+
+#[test]
+fn apply_refuses_files_that_changed_since_the_plan() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "a.txt", "first");
+    write(src.path(), "b.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    write(src.path(), "a.txt", "from the source");
+    write(src.path(), "b.txt", "from the source");
+    let plan = Syncer::new(options.clone()).plan(&mut ()).unwrap();
+    assert_eq!(plan.get_operations().len(), 2);
+
+    // edited on the destination after the plan was made
+    write(dest.path(), "a.txt", "from the destination");
+    let summary = Syncer::new(options).apply(&plan, &mut ()).unwrap();
+    assert_eq!(summary.get_errors(), 1);
+    assert_eq!(summary.get_copied(), 1);
+    assert_eq!(read(dest.path(), "a.txt"), "from the destination");
+    assert_eq!(read(dest.path(), "b.txt"), "from the source");
+}
+
+#[test]
+fn a_refused_apply_keeps_the_saved_sync_state() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "a.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    let tracker = dest.path().join(".hard_sync_cli").join("tracker.json");
+    let saved = fs::read(&tracker).unwrap();
+    write(src.path(), "a.txt", "from the source");
+    let plan = Syncer::new(options.clone()).plan(&mut ()).unwrap();
+
+    write(dest.path(), "a.txt", "from the destination");
+    let summary = Syncer::new(options).apply(&plan, &mut ()).unwrap();
+    assert_eq!(summary.get_errors(), 1);
+    assert_eq!(fs::read(&tracker).unwrap(), saved);
+}
+
+#[test]
+fn resume_skips_the_operations_the_journal_finished() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "a.txt", "first");
+    write(src.path(), "b.txt", "first");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    write(src.path(), "a.txt", "second");
+    write(src.path(), "b.txt", "second");
+    // a sync that recorded a.txt as done and then died
+    let plan = Syncer::new(options.clone()).plan(&mut ()).unwrap();
+    SyncJournal::start(dest.path(), &plan).unwrap().record("a.txt").unwrap();
+
+    let summary = Syncer::new(options).resume(&mut ()).unwrap();
+    assert_eq!(summary.get_copied(), 1);
+    assert_eq!(summary.get_errors(), 0);
+    assert_eq!(read(dest.path(), "a.txt"), "first");
+    assert_eq!(read(dest.path(), "b.txt"), "second");
+    assert!(!SyncJournal::exists(dest.path()));
+}
+
+#[cfg(unix)]
+#[test]
+fn archive_sync_updates_the_metadata_of_unchanged_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "mode.txt", "mode");
+    write(src.path(), "time.txt", "time");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true).with_preserve(PreserveMetadata::archive());
+    Syncer::new(options.clone()).run(&mut ()).unwrap();
+    fs::set_permissions(src.path().join("mode.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(src.path().join("time.txt")).unwrap().set_modified(mtime).unwrap();
+
+    let plan = Syncer::new(options.clone()).plan(&mut ()).unwrap();
+    let mut operations: Vec<(&str, OperationKind)> = plan.get_operations().iter().map(|op| (op.get_path(), op.get_kind())).collect();
+    operations.sort_by_key(|(path, _)| *path);
+    assert_eq!(operations, [("mode.txt", OperationKind::Metadata), ("time.txt", OperationKind::Metadata)]);
+
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_updated(), 2);
+    assert_eq!(summary.get_copied(), 0);
+    assert_eq!(fs::metadata(dest.path().join("mode.txt")).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(fs::metadata(dest.path().join("time.txt")).unwrap().modified().unwrap(), mtime);
+}
+
+#[cfg(unix)]
+#[test]
+fn links_are_copied_followed_or_skipped_and_never_leave_the_tree() {
+    use std::os::unix::fs::symlink;
+
+    let outside = tempfile::tempdir().unwrap();
+    write(outside.path(), "secret.txt", "secret");
+    for links in [LinkPolicy::Skip, LinkPolicy::Copy, LinkPolicy::Follow] {
+        let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(src.path(), "real.txt", "real");
+        write(src.path(), "dir/inner.txt", "inner");
+        symlink("real.txt", src.path().join("link.txt")).unwrap();
+        symlink("dir", src.path().join("dir_link")).unwrap();
+        // points back at the directory it is in
+        symlink("..", src.path().join("dir/loop")).unwrap();
+        symlink(outside.path().join("secret.txt"), src.path().join("outside.txt")).unwrap();
+
+        let options = SyncOptions::new(src.path(), dest.path()).with_init(true).with_links(links);
+        let summary = Syncer::new(options).run(&mut ()).unwrap();
+        assert_eq!(summary.get_errors(), 0, "{:?}", links);
+        assert!(fs::symlink_metadata(dest.path().join("outside.txt")).is_err(), "{:?}", links);
+        assert_eq!(read(dest.path(), "dir/inner.txt"), "inner");
+        match links {
+            LinkPolicy::Skip => {
+                assert_eq!(names(dest.path()), ["dir", "real.txt"]);
+                assert_eq!(names(&dest.path().join("dir")), ["inner.txt"]);
+            }
+            LinkPolicy::Copy => {
+                assert_eq!(names(dest.path()), ["dir", "dir_link", "link.txt", "real.txt"]);
+                assert_eq!(fs::read_link(dest.path().join("link.txt")).unwrap(), Path::new("real.txt"));
+                assert_eq!(fs::read_link(dest.path().join("dir_link")).unwrap(), Path::new("dir"));
+                assert_eq!(fs::read_link(dest.path().join("dir/loop")).unwrap(), Path::new(".."));
+            }
+            LinkPolicy::Follow => {
+                assert_eq!(names(dest.path()), ["dir", "dir_link", "link.txt", "real.txt"]);
+                assert!(!fs::symlink_metadata(dest.path().join("link.txt")).unwrap().is_symlink());
+                assert_eq!(read(dest.path(), "link.txt"), "real");
+                assert_eq!(read(dest.path(), "dir_link/inner.txt"), "inner");
+                // the loop is skipped wherever it is reached from
+                assert_eq!(names(&dest.path().join("dir")), ["inner.txt"]);
+                assert_eq!(names(&dest.path().join("dir_link")), ["inner.txt"]);
+            }
+        }
+    }
+}
+
+#[test]
+fn empty_directories_are_created_and_mirrored_away() {
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    fs::create_dir_all(src.path().join("empty/nested")).unwrap();
+    fs::create_dir(src.path().join("alone")).unwrap();
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_mode(SyncMode::Mirror).with_init(true);
+    let summary = Syncer::new(options.clone()).run(&mut ()).unwrap();
+    assert_eq!(summary.get_dirs_created(), 3);
+    assert_eq!(names(dest.path()), ["alone", "empty"]);
+    assert_eq!(names(&dest.path().join("empty")), ["nested"]);
+
+    fs::remove_dir_all(src.path().join("empty")).unwrap();
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_dirs_removed(), 2);
+    assert_eq!(summary.get_errors(), 0);
+    assert_eq!(names(dest.path()), ["alone"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_link_groups_are_linked_again_in_the_destination() {
+    use std::os::unix::fs::MetadataExt;
+
+    let (src, dest) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write(src.path(), "a.txt", "shared");
+    fs::hard_link(src.path().join("a.txt"), src.path().join("b.txt")).unwrap();
+    fs::create_dir(src.path().join("dir")).unwrap();
+    fs::hard_link(src.path().join("a.txt"), src.path().join("dir/c.txt")).unwrap();
+    write(src.path(), "alone.txt", "shared");
+
+    let options = SyncOptions::new(src.path(), dest.path()).with_init(true);
+    let summary = Syncer::new(options).run(&mut ()).unwrap();
+    assert_eq!(summary.get_copied(), 2);
+    assert_eq!(summary.get_hard_linked(), 2);
+    assert_eq!(summary.get_errors(), 0);
+
+    let inode = |path: &str| fs::metadata(dest.path().join(path)).unwrap().ino();
+    assert_eq!(inode("a.txt"), inode("b.txt"));
+    assert_eq!(inode("a.txt"), inode("dir/c.txt"));
+    assert_ne!(inode("a.txt"), inode("alone.txt"));
+    assert_eq!(fs::metadata(dest.path().join("a.txt")).unwrap().nlink(), 3);
+    assert_eq!(read(dest.path(), "dir/c.txt"), "shared");
+}